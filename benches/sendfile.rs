@@ -0,0 +1,70 @@
+extern crate fht2plib;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fht2plib::{config::Route, service::ServerBuilder};
+use tokio::runtime::Builder;
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+const FILE_SIZE: usize = 1024 * 1024 * 64;
+
+// cargo bench --bench sendfile
+fn download(addr: SocketAddr, path: &str) -> u64 {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr).unwrap();
+    io::copy(&mut stream, &mut io::sink()).unwrap()
+}
+
+// the body of the response
+fn download_body(addr: SocketAddr, path: &str) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr).unwrap();
+    let mut resp = vec![];
+    stream.read_to_end(&mut resp).unwrap();
+    let head = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    resp.split_off(head + 4)
+}
+
+fn sendfile_benchmark(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("fht2p-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let content = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(dir.join("big.bin"), &content).unwrap();
+
+    let mut rt = Builder::new().threaded_scheduler().enable_all().build().unwrap();
+    let servers = [("sendfile", true), ("buffered", false)]
+        .iter()
+        .map(|(name, sendfile)| {
+            let mut builder = ServerBuilder::default()
+                .addr("127.0.0.1:0".parse().unwrap())
+                .route(Route::new("/", dir.to_str().unwrap()));
+            builder.config_mut().sendfile = *sendfile;
+            builder.config_mut().compress_level = 0;
+            (*name, rt.block_on(builder.serve()).unwrap())
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("download 64MiB");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64)).sample_size(10);
+    for (name, server) in &servers {
+        let addr = server.addr();
+        assert!(
+            download_body(addr, "/big.bin") == content,
+            "{} sent the different bytes",
+            name
+        );
+        group.bench_function(*name, |b| b.iter(|| download(addr, "/big.bin")));
+    }
+    group.finish();
+
+    for (_, server) in servers {
+        rt.block_on(server.shutdown()).unwrap();
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, sendfile_benchmark);
+criterion_main!(benches);
@@ -8,8 +8,10 @@ use std::path::Path;
 use std::{env, fs, str};
 
 use crate::{
-    config::{Auth, Cert, Config, CorsConfig, ProxyRoute, Route},
+    base::vhost::VHost,
+    config::{Auth, Cert, Cidr, Config, CorsConfig, HeaderRule, ProxyRoute, Rewrite, Route},
     consts::*,
+    daemon,
     logger::{logger_init, JoinHandle},
     process_exit,
 };
@@ -26,6 +28,11 @@ pub fn parse() -> (Config, JoinHandle) {
     let default_cache = config.cache_secs.to_string();
     let default_compress = config.compress_level.to_string();
     let default_magic_size = config.magic_limit.to_string();
+    let default_worker_threads = config.worker_threads.to_string();
+    let default_blocking_threads = config.blocking_threads.to_string();
+    let default_thread_stack_size = config.thread_stack_size.to_string();
+    let default_pid_file = daemon::default_pid_file();
+    let default_log_file = daemon::default_log_file();
     let app = {
         App::new(NAME)
             .version(VERSION)
@@ -163,6 +170,30 @@ pub fn parse() -> (Config, JoinHandle) {
                             .map_err(|e| format!("invalid value for cache-secs: {}", e))
                     }),
             )
+            .arg(
+                Arg::with_name("worker-threads")
+                    .long("worker-threads")
+                    .takes_value(true)
+                    .default_value(&default_worker_threads)
+                    .help("Set the number of runtime's worker threads")
+                    .validator(|s| threads_validator(&s, "worker-threads")),
+            )
+            .arg(
+                Arg::with_name("blocking-threads")
+                    .long("blocking-threads")
+                    .takes_value(true)
+                    .default_value(&default_blocking_threads)
+                    .help("Set the max number of threads for blocking io(read files, compress..)")
+                    .validator(|s| threads_validator(&s, "blocking-threads")),
+            )
+            .arg(
+                Arg::with_name("thread-stack-size")
+                    .long("thread-stack-size")
+                    .takes_value(true)
+                    .default_value(&default_thread_stack_size)
+                    .help("Set the stack size(bytes) of runtime's threads")
+                    .validator(|s| threads_validator(&s, "thread-stack-size")),
+            )
             .arg(
                 Arg::with_name("proxy")
                     .long("proxy")
@@ -175,6 +206,44 @@ pub fn parse() -> (Config, JoinHandle) {
                             .map_err(|e| format!("invalid value for proxy: {}", e))
                     }),
             )
+            .arg(
+                Arg::with_name("trusted-proxies")
+                    .long("trusted-proxies")
+                    .short("T")
+                    .takes_value(true)
+                    .help("Set the reverse proxies(ip or cidr, separated by ',') whose X-Forwarded-For, X-Real-IP and Forwarded headers are trusted")
+                    .validator(|s| {
+                        parse_cidrs(&s)
+                            .map(|_| ())
+                            .map_err(|e| format!("invalid value for trusted-proxies: {}", e))
+                    }),
+            )
+            .arg(
+                Arg::with_name("daemon")
+                    .long("daemon")
+                    .short("D")
+                    .help("Run in background, write the pid to pid-file and the logs to log-file(unix only)"),
+            )
+            .arg(
+                Arg::with_name("stop")
+                    .long("stop")
+                    .conflicts_with("daemon")
+                    .help("Stop the daemon recorded in pid-file"),
+            )
+            .arg(
+                Arg::with_name("pid-file")
+                    .long("pid-file")
+                    .takes_value(true)
+                    .default_value(&default_pid_file)
+                    .help("Set the pid file for daemon"),
+            )
+            .arg(
+                Arg::with_name("log-file")
+                    .long("log-file")
+                    .takes_value(true)
+                    .default_value(&default_log_file)
+                    .help("Set the log file for daemon"),
+            )
             .arg(
                 Arg::with_name("ip")
                     .long("ip")
@@ -220,21 +289,46 @@ pub fn parse() -> (Config, JoinHandle) {
         config_print();
     }
 
-    let mut join_handle = logger_init(matches.occurrences_of("verbose"));
+    // --stop, --daemon: before the logger thread spawned
+    let pid_file = matches.value_of("pid-file").map(|s| s.to_owned()).unwrap();
+    if matches.is_present("stop") {
+        let code = daemon::stop(&pid_file).map(|_| 0).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            1
+        });
+        process_exit(code);
+    }
+
+    let is_daemon = matches.is_present("daemon");
+    if is_daemon {
+        let log_file = matches.value_of("log-file").unwrap();
+        if let Err(e) = daemon::daemonize(&pid_file, log_file) {
+            eprintln!("{}", e);
+            process_exit(1);
+        }
+    }
+    let pid_file = if is_daemon { Some(pid_file) } else { None };
+
+    let mut join_handle = logger_init(matches.occurrences_of("verbose"), !is_daemon);
     let exit_with_msg = |e: String| {
         error!("{}", e);
+        daemon::notify_startup(Err(&e));
+        if let Some(pid_file) = pid_file.as_ref() {
+            daemon::remove_pid_file(pid_file);
+        }
         join_handle.join();
         process_exit(1);
     };
 
     //-c/--config option，use it if it exists
     if let Some(s) = matches.value_of("config") {
-        let config = Config::load_from_file(&s).map_err(exit_with_msg).unwrap();
+        let mut config = Config::load_from_file(&s).map_err(exit_with_msg).unwrap();
+        config.pid_file = pid_file;
         return (config.show_qrcode(qr), join_handle);
     }
 
     // 命令行有没有参数？有就解析参数，没有就寻找配置文件，再没有就使用默认配置。
-    let conf = if args_is_empty {
+    let mut conf = if args_is_empty {
         match get_config_path() {
             Some(s) => Config::load_from_file(&s).map_err(exit_with_msg).unwrap(),
             None => Config::load_from_STRING(),
@@ -256,12 +350,25 @@ pub fn parse() -> (Config, JoinHandle) {
         matches
             .value_of("compress")
             .map(|cp| config.compress_level = cp.parse::<u32>().unwrap());
+        matches
+            .value_of("worker-threads")
+            .map(|wt| config.worker_threads = wt.parse::<usize>().unwrap());
+        matches
+            .value_of("blocking-threads")
+            .map(|bt| config.blocking_threads = bt.parse::<usize>().unwrap());
+        matches
+            .value_of("thread-stack-size")
+            .map(|ts| config.thread_stack_size = ts.parse::<usize>().unwrap());
 
         config.addr = SocketAddr::new(server.ip, server.port);
         config.auth = matches.value_of("auth").map(|cp| cp.parse::<Auth>().unwrap());
         config.cert = matches.value_of("cert").map(|cp| cp.parse::<Cert>().unwrap());
         config.proxy = matches.value_of("proxy").map(|s| ProxyRoute::new(true, s).into());
         config.keep_alive = !matches.is_present("keepalive");
+        config.trusted_proxies = matches
+            .value_of("trusted-proxies")
+            .map(|s| parse_cidrs(s).unwrap())
+            .unwrap_or_default();
         matches.values_of_lossy("PATH").map(|args| routes = args);
 
         config.routes = args_paths_to_route(&routes[..], |r| {
@@ -278,22 +385,49 @@ pub fn parse() -> (Config, JoinHandle) {
         config
     };
 
+    conf.pid_file = pid_file;
     (conf.show_qrcode(qr), join_handle)
 }
 
+fn threads_validator(s: &str, name: &str) -> Result<(), String> {
+    s.parse::<usize>()
+        .map_err(|e| format!("invalid value for {}: {}", name, e))
+        .and_then(|x| {
+            if x > 0 {
+                Ok(())
+            } else {
+                Err(format!("invalid value for {}: should bigger than 0", name))
+            }
+        })
+}
+
+fn parse_cidrs(s: &str) -> crate::how::Result<Vec<Cidr>> {
+    s.split(',').filter(|c| !c.trim().is_empty()).map(|c| c.parse()).collect()
+}
+
 fn config_print() {
     println!("{}", CONFIG_STRING);
     process_exit(0);
 }
 
-// not contains other than -v*, -Q, --qr-code/--verbose, but -vs ?
+// not contains other than -v*, -Q, -D, --qr-code/--verbose/--daemon/--pid-file/--log-file, but -vs ?
 pub fn args_is_empty<S: AsRef<str>>(args: impl Iterator<Item = S>) -> bool {
-    let regex = Regex::new(r"^((-[vQ]{1,})|(--verbose)|(--qr-code))$").unwrap();
+    let regex = Regex::new(r"^((-[vQD]{1,})|(--verbose)|(--qr-code)|(--daemon)|(--(pid|log)-file=.+))$").unwrap();
+    let mut args = args.skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--pid-file" | "--log-file" => {
+                if args.next().is_none() {
+                    return false;
+                }
+            }
+            arg if regex.is_match(arg) => {}
+            _ => return false,
+        }
+    }
 
-    args.skip(1).all(|arg| {
-        // arg.as_ref().starts_with("-v") || arg.as_ref().starts_with("-Q") || ["--verbose", "--qr-code"].contains(&arg.as_ref())
-        regex.is_match(arg.as_ref())
-    })
+    true
 }
 
 #[test]
@@ -305,6 +439,11 @@ fn args_is_empty_test() {
     assert!(args_is_empty(["", "-vv"].iter()));
     assert!(args_is_empty(["", "-vQ"].iter()));
     assert!(args_is_empty(["", "-QQ"].iter()));
+    assert!(args_is_empty(["", "-vD"].iter()));
+    assert!(args_is_empty(
+        ["", "--daemon", "--pid-file", "a.pid", "--log-file", "a.log"].iter()
+    ));
+    assert!(args_is_empty(["", "--daemon", "--pid-file=a.pid"].iter()));
 
     assert!(!args_is_empty(["", "-vs"].iter()));
     assert!(!args_is_empty(["", "-Qr"].iter()));
@@ -322,6 +461,8 @@ fn args_is_empty_test() {
     assert!(!args_is_empty(["", "./-v"].iter()));
     assert!(!args_is_empty(["", "-"].iter()));
     assert!(!args_is_empty(["", "--"].iter()));
+    assert!(!args_is_empty(["", "--daemon", "--pid-file"].iter()));
+    assert!(!args_is_empty(["", "--pid-file=", "-p", "80"].iter()));
     assert!(!args_is_empty(["", ""].iter()));
 }
 
@@ -333,7 +474,7 @@ pub struct Server {
 
 impl Default for Server {
     fn default() -> Server {
-        let addr = SERVER_ADDR.get();
+        let addr = *SERVER_ADDR;
         Self::new(addr.ip(), addr.port())
     }
 }
@@ -357,7 +498,12 @@ impl Into<SocketAddr> for Server {
 pub struct Fht2p {
     setting: Setting,
     proxy: Option<ProxyRoute>,
+    #[serde(default)]
     routes: Map<String, Route>,
+    #[serde(default)]
+    vhosts: Map<String, Map<String, Route>>,
+    #[serde(default)]
+    rewrites: Vec<Rewrite>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -366,25 +512,44 @@ pub struct Setting {
     #[serde(default = "Server::get_sa")]
     addr: SocketAddr,
     keep_alive: bool,
+    sendfile: Option<bool>,
     magic_limit: u64,
     cache_secs: u32,
     compress_level: u32,
+    brotli_level: Option<u32>,
+    zstd_level: Option<i32>,
     auth: Option<Auth>,
     cert: Option<Cert>,
     cors: Option<CorsConfig>,
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+    #[serde(default)]
+    headers: Vec<HeaderRule>,
+    #[serde(default)]
+    error_pages: Map<String, String>,
+    worker_threads: Option<usize>,
+    blocking_threads: Option<usize>,
+    thread_stack_size: Option<usize>,
 }
 
 impl Config {
-    fn load_from_file(path: &str) -> Result<Self, String> {
+    pub(crate) fn load_from_file(path: &str) -> Result<Self, String> {
         debug!("Config.load_from_file: {}", path);
         let str = fs::read_to_string(path).map_err(|e| format!("config file('{}') read failed: {}", path, e))?;
-        Self::load_from_str(path, &str)
+        let mut config = Self::load_from_str(path, &str)?;
+        config.config_path = Some(path.to_owned());
+        Ok(config)
     }
     fn load_from_str(file_name: &str, json: &str) -> Result<Config, String> {
         let mut config = Self::default();
 
-        let Fht2p { setting, proxy, routes } =
-            json5::from_str(json).map_err(|e| format!("config file('{}') parse failed: {}", file_name, e))?;
+        let Fht2p {
+            setting,
+            proxy,
+            routes,
+            vhosts,
+            rewrites,
+        } = json5::from_str(json).map_err(|e| format!("config file('{}') parse failed: {}", file_name, e))?;
 
         let Setting {
             addr,
@@ -392,31 +557,118 @@ impl Config {
             cert,
             cors,
             keep_alive,
+            sendfile,
             magic_limit,
             cache_secs,
             compress_level,
+            brotli_level,
+            zstd_level,
+            trusted_proxies,
+            headers,
+            error_pages,
+            worker_threads,
+            blocking_threads,
+            thread_stack_size,
         } = setting;
 
         config.addr = addr;
         config.cert = cert;
         config.auth = auth;
         config.keep_alive = keep_alive;
+        config.sendfile = sendfile.unwrap_or(config.sendfile);
         config.cache_secs = cache_secs;
         config.magic_limit = magic_limit;
         config.compress_level = compress_level;
+        config.brotli_level = brotli_level.unwrap_or(config.brotli_level);
+        config.zstd_level = zstd_level.unwrap_or(config.zstd_level);
         config.proxy = proxy.map(|pc| pc.into());
         config.cors = cors.unwrap_or_default();
+        config.headers = headers;
+        config.error_pages = error_pages;
+        config.trusted_proxies = trusted_proxies
+            .iter()
+            .map(|s| s.parse())
+            .collect::<crate::how::Result<_>>()
+            .map_err(|e| format!("config file('{}')'s trustedProxies invalid: {}", file_name, e))?;
+
+        let threads = vec![
+            ("workerThreads", worker_threads, &mut config.worker_threads),
+            ("blockingThreads", blocking_threads, &mut config.blocking_threads),
+            ("threadStackSize", thread_stack_size, &mut config.thread_stack_size),
+        ];
+        for (name, value, field) in threads {
+            match value {
+                Some(0) => return Err(format!("config file('{}')'s {} should bigger than 0", file_name, name)),
+                Some(v) => *field = v,
+                None => {}
+            }
+        }
 
         config.routes = routes;
-        if config.routes.is_empty() {
+        config.vhosts = vhosts;
+        config.rewrites = rewrites;
+        if config.routes.is_empty() && config.vhosts.is_empty() {
             return Err(format!("'{}''s routes is empty", file_name));
-        } else {
-            for (url, route) in &mut config.routes {
+        }
+
+        if config.compress_level > 9 {
+            return Err(format!("config file('{}')'s compressLevel should between 0~9", file_name));
+        }
+        if config.brotli_level > 11 {
+            return Err(format!("config file('{}')'s brotliLevel should between 0~11", file_name));
+        }
+        if !(1..=21).contains(&config.zstd_level) {
+            return Err(format!("config file('{}')'s zstdLevel should between 1~21", file_name));
+        }
+
+        let fix_routes = |vhost: &str, routes: &mut Map<String, Route>| {
+            for (url, route) in routes {
                 route.url = url.clone();
-                if !Path::new(&route.path).exists() {
-                    warn!("'{}''s routes({:?}: {:?}) is not exists", file_name, url, route.path);
+                // the path of pattern route contains captures
+                if route.upstream.is_none() && !route.path.contains('{') && !Path::new(&route.path).exists() {
+                    warn!("'{}''s routes({}{:?}: {:?}) is not exists", file_name, vhost, url, route.path);
+                }
+                if route.compress_level.map(|l| l > 9).unwrap_or_default() {
+                    return Err(format!(
+                        "config file('{}')'s routes({}{:?})'s compressLevel should between 0~9",
+                        file_name, vhost, url
+                    ));
+                }
+                if route.index_files().iter().any(|f| f.is_empty() || f.contains('/')) {
+                    return Err(format!(
+                        "config file('{}')'s routes({}{:?})'s indexFiles should be file names",
+                        file_name, vhost, url
+                    ));
+                }
+                if route
+                    .try_extensions
+                    .iter()
+                    .any(|e| e.len() < 2 || !e.starts_with('.') || e.contains('/'))
+                {
+                    return Err(format!(
+                        "config file('{}')'s routes({}{:?})'s tryExtensions should be like \".html\"",
+                        file_name, vhost, url
+                    ));
+                }
+                if let Some(fc) = route
+                    .file_cache
+                    .filter(|fc| fc.max_file_size == 0 || fc.max_file_size > fc.max_size)
+                {
+                    return Err(format!(
+                        "config file('{}')'s routes({}{:?})'s fileCache.maxFileSize({}) should between 1~maxSize({})",
+                        file_name, vhost, url, fc.max_file_size, fc.max_size
+                    ));
                 }
             }
+            Ok(())
+        };
+
+        fix_routes("", &mut config.routes)?;
+        for (vhost, routes) in &mut config.vhosts {
+            vhost
+                .parse::<VHost>()
+                .map_err(|e| format!("config file('{}')'s vhosts invalid: {}", file_name, e))?;
+            fix_routes(vhost, routes)?;
         }
 
         Ok(config)
@@ -1,19 +1,24 @@
-use crate::{config, service::GlobalState, typemap::TypeMap};
+use crate::{config, service::SharedState, typemap::TypeMap};
+
+use std::sync::Arc;
 
 pub type Ctx = TypeMap;
 
 pub mod ctxs {
     use super::*;
 
-    pub const CAPACITY: usize = 9;
+    pub const CAPACITY: usize = 10;
 
-    pub type State = GlobalState;
-    pub type Route = &'static config::Route;
+    pub type State = SharedState;
+    pub type Route = Arc<config::Route>;
+    pub type Captures = crate::base::matcher::Captures;
     pub type ReqMethod = hyper::Method;
     pub type ReqStart = std::time::Instant;
     pub type ReqUri = hyper::Uri;
     pub type ReqPath = String;
     pub type ReqPathCs = Vec<String>;
+    // the query merged by the internal rewrite
+    pub type ReqQuery = Option<String>;
     pub type IsProxy = ();
     pub type Cors = bool;
 }
@@ -0,0 +1,220 @@
+use globset::{GlobBuilder, GlobMatcher};
+
+use std::{fs, path::Path};
+
+use crate::how::Result;
+
+/// The gitignore-style patterns in it apply to its directory and the subdirectories
+pub const IGNORE_FILE: &str = ".fht2pignore";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: GlobMatcher,
+    // `!pattern` includes the excluded again
+    negated: bool,
+    // `pattern/` only matches the directories
+    dir_only: bool,
+    // `/pattern` or `a/b` matches the path relative to the base, others match the name
+    anchored: bool,
+}
+
+/// Gitignore-style patterns, the last matched wins
+#[derive(Debug, Default, Clone)]
+pub struct Excludes {
+    rules: Vec<Rule>,
+}
+
+impl Excludes {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let rules = patterns
+            .iter()
+            .filter_map(|p| Rule::parse(p.as_ref()).transpose())
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    // the invalid lines are skipped
+    fn from_file(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        let rules = content
+            .lines()
+            .filter_map(|line| {
+                Rule::parse(line)
+                    .map_err(|e| warn!("{}'s pattern({:?}) invalid: {}", path.display(), line, e))
+                    .ok()
+                    .flatten()
+            })
+            .collect();
+        Some(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // None if no rule matched
+    fn matched(&self, relpath: &str, is_dir: bool) -> Option<bool> {
+        let name = relpath.rsplit('/').next().unwrap_or_default();
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| (!r.dir_only || is_dir) && r.glob.is_match(if r.anchored { relpath } else { name }))
+            .map(|r| !r.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Option<Self>> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(p) => (true, p),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(p) => (true, p),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return Err(format_err!("empty pattern: {:?}", line));
+        }
+
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format_err!("invalid pattern {:?}: {}", line, e))?
+            .compile_matcher();
+
+        Ok(Some(Self {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+        }))
+    }
+}
+
+/// The excludes of route and the ignore files from the root of route to a directory
+#[derive(Debug, Clone)]
+pub struct Excluder<'a> {
+    // (the depth of base, excludes)
+    layers: Vec<(usize, Excludes)>,
+    route: &'a Excludes,
+    // the components of the directory under the root
+    cs: Vec<&'a str>,
+}
+
+impl<'a> Excluder<'a> {
+    /// Walk the `cs` under `root`, None if it or any parent of it is excluded
+    pub fn walk(root: &Path, route: &'a Excludes, cs: &'a [String], is_dir: bool) -> Option<Self> {
+        let mut this = Self {
+            layers: vec![],
+            route,
+            cs: Vec::with_capacity(cs.len()),
+        };
+        let mut dir = root.to_owned();
+        this.load(&dir);
+
+        for (idx, c) in cs.iter().enumerate() {
+            let c_is_dir = is_dir || idx + 1 < cs.len();
+            if this.is_excluded(c, c_is_dir) {
+                return None;
+            }
+            if c_is_dir {
+                this.cs.push(c.as_str());
+                dir.push(c);
+                this.load(&dir);
+            }
+        }
+
+        Some(this)
+    }
+
+    fn load(&mut self, dir: &Path) {
+        if let Some(excludes) = Excludes::from_file(&dir.join(IGNORE_FILE)) {
+            self.layers.push((self.cs.len(), excludes));
+        }
+    }
+
+    /// Whether the entry `name` of the directory is excluded
+    pub fn is_excluded(&self, name: &str, is_dir: bool) -> bool {
+        if name == IGNORE_FILE {
+            return true;
+        }
+
+        let relpath = |base: usize| {
+            let mut path = self.cs[base..].join("/");
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(name);
+            path
+        };
+
+        // the deeper ignore files override the shallower ones and the route's
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|(base, excludes)| excludes.matched(&relpath(*base), is_dir))
+            .or_else(|| self.route.matched(&relpath(0), is_dir))
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn excludes_test() {
+    let excludes = Excludes::new(&["# secrets", ".git/", "*.key", "!public.key", "/build", "docs/**/*.tmp", ""]).unwrap();
+    let matched = |path: &str, is_dir: bool| excludes.matched(path, is_dir).unwrap_or_default();
+
+    assert!(matched(".git", true));
+    assert!(matched("a/.git", true));
+    assert!(!matched(".git", false));
+    assert!(matched("a/b/server.key", false));
+    assert!(!matched("a/public.key", false));
+    assert!(matched("build", true));
+    assert!(!matched("a/build", true));
+    assert!(matched("docs/a/b/c.tmp", false));
+    assert!(!matched("a/docs/c.tmp", false));
+    assert!(excludes.matched("readme.md", false).is_none());
+
+    assert!(Excludes::new(&["a["]).is_err());
+    assert!(Excludes::new(&["/"]).is_err());
+    assert!(Excludes::new(&["#", " "]).unwrap().is_empty());
+}
+
+#[test]
+fn excluder_test() {
+    let root = std::env::temp_dir().join(format!("fht2p-excluder-{}", std::process::id()));
+    fs::create_dir_all(root.join("a/b")).unwrap();
+    fs::write(root.join("a").join(IGNORE_FILE), "*.log\n/b/c\n").unwrap();
+
+    let route = Excludes::new(&["node_modules", "*.tmp", "!keep.log"]).unwrap();
+    let cs = |path: &str| path.split('/').map(|c| c.to_owned()).collect::<Vec<_>>();
+    let walk = |path: &str, is_dir: bool| Excluder::walk(&root, &route, &cs(path), is_dir).is_some();
+
+    assert!(walk("a/b", true));
+    assert!(walk("a/x.txt", false));
+    assert!(!walk("a/x.log", false));
+    // the ignore file overrides the route's
+    assert!(!walk("a/keep.log", false));
+    assert!(walk("keep.log", false));
+    assert!(!walk("a/b/c", false));
+    assert!(walk("b/c", false));
+    assert!(!walk("node_modules/a/b", true));
+    assert!(!walk(&format!("a/{}", IGNORE_FILE), false));
+
+    let excluder = Excluder::walk(&root, &route, &[], true).unwrap();
+    assert!(excluder.is_excluded("x.tmp", false));
+    assert!(!excluder.is_excluded("x.log", false));
+    let cs = cs("a");
+    let excluder = Excluder::walk(&root, &route, &cs, true).unwrap();
+    assert!(excluder.is_excluded("x.log", false));
+    assert!(excluder.is_excluded(IGNORE_FILE, false));
+
+    fs::remove_dir_all(&root).unwrap();
+}
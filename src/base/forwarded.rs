@@ -0,0 +1,140 @@
+use hyper::header::{self, HeaderName};
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{base::Request, config::Cidr};
+
+/// The peers allowed to tell us the real client address
+#[derive(Debug, Default, Clone)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    pub fn new(cidrs: &[Cidr]) -> Self {
+        Self(cidrs.to_vec())
+    }
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|c| c.contains(ip))
+    }
+
+    /// Use `Forwarded`, `X-Forwarded-For` or `X-Real-IP` only if the peer is trusted,
+    /// the nearest untrusted hop of the chain is the client.
+    pub fn client_addr(&self, req: &Request, peer: SocketAddr) -> SocketAddr {
+        if !self.is_trusted(&peer.ip()) {
+            return peer;
+        }
+
+        let x_forwarded_for = HeaderName::from_static("x-forwarded-for");
+        let x_real_ip = HeaderName::from_static("x-real-ip");
+
+        let hops = if req.headers().contains_key(header::FORWARDED) {
+            header_values(req, header::FORWARDED)
+                .map(|v| forwarded_for(v).map(parse_node).unwrap_or(None))
+                .collect::<Vec<_>>()
+        } else if req.headers().contains_key(&x_forwarded_for) {
+            header_values(req, x_forwarded_for).map(parse_node).collect()
+        } else {
+            header_values(req, x_real_ip).map(parse_node).collect()
+        };
+
+        let mut addr = peer;
+        for hop in hops.into_iter().rev() {
+            match hop {
+                Some(hop) => {
+                    addr = hop;
+                    if !self.is_trusted(&hop.ip()) {
+                        break;
+                    }
+                }
+                // unknown or obfuscated
+                None => break,
+            }
+        }
+
+        debug!("peer: {}, client: {}", peer, addr);
+        addr
+    }
+}
+
+// X-Forwarded-For: 203.0.113.195, 70.41.3.18
+fn header_values(req: &Request, key: HeaderName) -> impl Iterator<Item = &str> {
+    req.headers()
+        .get_all(key)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+}
+
+// Forwarded: for=192.0.2.60;proto=http;by=203.0.113.43
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let pair = pair.trim();
+        if pair.len() > 4 && pair[..4].eq_ignore_ascii_case("for=") {
+            Some(&pair[4..])
+        } else {
+            None
+        }
+    })
+}
+
+// 192.0.2.60, 192.0.2.60:4711, "[2001:db8:cafe::17]:4711", 2001:db8:cafe::17
+pub(crate) fn parse_node(node: &str) -> Option<SocketAddr> {
+    let node = node.trim().trim_matches('"');
+
+    node.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 0))
+        .or_else(|_| node.parse::<SocketAddr>())
+        .or_else(|_| {
+            node.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, 0))
+        })
+        .ok()
+}
+
+#[test]
+fn parse_node_test() {
+    assert_eq!(parse_node("192.0.2.60"), Some("192.0.2.60:0".parse().unwrap()));
+    assert_eq!(parse_node(" 192.0.2.60:4711"), Some("192.0.2.60:4711".parse().unwrap()));
+    assert_eq!(
+        parse_node("\"[2001:db8::17]:4711\""),
+        Some("[2001:db8::17]:4711".parse().unwrap())
+    );
+    assert_eq!(parse_node("\"[2001:db8::17]\""), Some("[2001:db8::17]:0".parse().unwrap()));
+    assert_eq!(parse_node("2001:db8::17"), Some("[2001:db8::17]:0".parse().unwrap()));
+    assert_eq!(parse_node("unknown"), None);
+    assert_eq!(parse_node("_hidden"), None);
+    assert_eq!(forwarded_for("proto=http;FOR=192.0.2.60;by=1.1.1.1"), Some("192.0.2.60"));
+    assert_eq!(forwarded_for("proto=http"), None);
+}
+
+#[test]
+fn client_addr_test() {
+    let trusted = TrustedProxies::new(&["127.0.0.1".parse().unwrap(), "10.0.0.0/8".parse().unwrap()]);
+    let proxy = "127.0.0.1:5000".parse::<SocketAddr>().unwrap();
+    let other = "192.168.1.1:5000".parse::<SocketAddr>().unwrap();
+    let req = |k: &str, v: &str| hyper::Request::get("/").header(k, v).body(Default::default()).unwrap();
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+    let r = req("X-Forwarded-For", "203.0.113.195, 10.1.1.1");
+    assert_eq!(trusted.client_addr(&r, proxy), addr("203.0.113.195:0"));
+    // spoofed by an untrusted peer
+    assert_eq!(trusted.client_addr(&r, other), other);
+    assert_eq!(TrustedProxies::default().client_addr(&r, proxy), proxy);
+
+    // the client prepends a fake address
+    let r = req("X-Forwarded-For", "1.1.1.1, 203.0.113.195");
+    assert_eq!(trusted.client_addr(&r, proxy), addr("203.0.113.195:0"));
+
+    let r = req("X-Real-IP", "203.0.113.195");
+    assert_eq!(trusted.client_addr(&r, proxy), addr("203.0.113.195:0"));
+
+    let r = req("Forwarded", "for=\"[2001:db8::17]:4711\";proto=https, for=10.0.0.2");
+    assert_eq!(trusted.client_addr(&r, proxy), addr("[2001:db8::17]:4711"));
+
+    let r = req("Forwarded", "for=unknown, for=10.0.0.2");
+    assert_eq!(trusted.client_addr(&r, proxy), addr("10.0.0.2:0"));
+
+    let r = req("X-Forwarded-For", "garbage");
+    assert_eq!(trusted.client_addr(&r, proxy), proxy);
+}
@@ -0,0 +1,239 @@
+use regex::Regex;
+
+use std::collections::BTreeMap as Map;
+
+use crate::how::Result;
+
+pub type Captures = Vec<(String, String)>;
+
+/// One component of the route's url: `doc`, `~{user}`, `{id:\d+}` or `*.rs`
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Pattern {
+        source: String,
+        regex: Regex,
+        names: Vec<String>,
+    },
+}
+
+impl Segment {
+    fn parse(s: &str) -> Result<Self> {
+        if !s.contains(['{', '*']) {
+            return Ok(Segment::Literal(s.to_owned()));
+        }
+
+        let mut re = String::with_capacity(s.len() * 2);
+        let mut names = vec![];
+        let mut rest = s;
+
+        re.push_str("^(?:");
+        while let Some(idx) = rest.find(['{', '*']) {
+            re.push_str(&regex::escape(&rest[..idx]));
+            rest = &rest[idx..];
+
+            if rest.starts_with('*') {
+                re.push_str("[^/]*");
+                rest = &rest[1..];
+                continue;
+            }
+
+            // the regex may contains `{n,m}`
+            let mut depth = 0;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|(idx, _)| idx)
+                .ok_or_else(|| format_err!("unclosed '{{' in route pattern: {:?}", s))?;
+
+            let capture = &rest[1..end];
+            let (name, pattern) = match capture.find(':') {
+                Some(idx) => (&capture[..idx], &capture[idx + 1..]),
+                None => (capture, "[^/]+"),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format_err!("invalid capture name {:?} in route pattern: {:?}", name, s));
+            }
+            if names.iter().any(|n| n == name) {
+                return Err(format_err!("duplicate capture name {:?} in route pattern: {:?}", name, s));
+            }
+
+            re.push_str(&format!("(?P<{}>{})", name, pattern));
+            names.push(name.to_owned());
+            rest = &rest[end + 1..];
+        }
+        re.push_str(&regex::escape(rest));
+        re.push_str(")$");
+
+        let regex = Regex::new(&re).map_err(|e| format_err!("invalid route pattern {:?}: {}", s, e))?;
+        Ok(Segment::Pattern {
+            source: s.to_owned(),
+            regex,
+            names,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    // (is_prefix, index)
+    values: Vec<(bool, usize)>,
+    literals: Map<String, Node>,
+    patterns: Vec<(Segment, Node)>,
+}
+
+impl Node {
+    // (depth, index, captures)
+    fn find(&self, cs: &[&str], depth: usize) -> Option<(usize, usize, Captures)> {
+        let mut matched = None;
+
+        if let Some(c) = cs.get(depth) {
+            // literal first, the deeper wins
+            matched = self.literals.get(*c).and_then(|n| n.find(cs, depth + 1));
+
+            for (segment, node) in &self.patterns {
+                if let Segment::Pattern { regex, names, .. } = segment {
+                    let found = regex.captures(c).and_then(|caps| {
+                        node.find(cs, depth + 1).map(|(found_depth, idx, mut captures)| {
+                            let values = names.iter().map(|n| (n.clone(), caps[n.as_str()].to_owned()));
+                            captures.splice(0..0, values);
+                            (found_depth, idx, captures)
+                        })
+                    });
+
+                    if found.as_ref().map(|f| f.0) > matched.as_ref().map(|m| m.0) {
+                        matched = found;
+                    }
+                }
+            }
+        }
+
+        matched.or_else(|| {
+            self.values
+                .iter()
+                .find(|(is_prefix, _)| *is_prefix || depth == cs.len())
+                .map(|&(_, idx)| (depth, idx, vec![]))
+        })
+    }
+}
+
+/// Segment trie for the urls of routes, a url ends with `/` matches the paths under it
+#[derive(Debug, Default)]
+pub struct Matcher {
+    root: Node,
+}
+
+impl Matcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, url: &str, idx: usize) -> Result<()> {
+        let mut node = &mut self.root;
+        for c in url.split('/').filter(|c| !c.is_empty()) {
+            node = match Segment::parse(c)? {
+                Segment::Literal(c) => node.literals.entry(c).or_default(),
+                Segment::Pattern { source, regex, names } => {
+                    let pos = node.patterns.iter().position(|(s, _)| match s {
+                        Segment::Pattern { source: s, .. } => *s == source,
+                        Segment::Literal(_) => false,
+                    });
+                    let pos = pos.unwrap_or_else(|| {
+                        node.patterns
+                            .push((Segment::Pattern { source, regex, names }, Node::default()));
+                        node.patterns.len() - 1
+                    });
+                    &mut node.patterns[pos].1
+                }
+            };
+        }
+
+        node.values.push((url.ends_with('/'), idx));
+        Ok(())
+    }
+
+    /// The index of the deepest route matched the `reqpath` and its captures
+    pub fn find(&self, reqpath: &str) -> Option<(usize, Captures)> {
+        let cs = reqpath.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+        self.root.find(&cs, 0).map(|(_, idx, captures)| (idx, captures))
+    }
+}
+
+/// The capture names of the route's url
+pub fn capture_names(url: &str) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for c in url.split('/').filter(|c| !c.is_empty()) {
+        if let Segment::Pattern { names: ns, .. } = Segment::parse(c)? {
+            names.extend(ns);
+        }
+    }
+    Ok(names)
+}
+
+/// Replace the `{name}`s of `template` with the captures
+pub fn expand(template: &str, captures: &[(String, String)]) -> String {
+    captures.iter().fold(template.to_owned(), |s, (name, value)| {
+        s.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+#[test]
+fn matcher_test() {
+    let mut matcher = Matcher::new();
+    let urls = [
+        "/",
+        "/doc/",
+        "/doc/readme.md",
+        "/builds/{branch}/latest/",
+        "/builds/master/latest/",
+        "/home/~{user}/",
+        "/issues/{id:\\d{1,5}}",
+        "/src/*.rs",
+    ];
+    for (idx, url) in urls.iter().enumerate() {
+        matcher.insert(url, idx).unwrap();
+    }
+
+    let find = |path: &str| matcher.find(path).map(|(idx, captures)| (urls[idx], captures));
+    let caps = |kvs: &[(&str, &str)]| kvs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Captures>();
+
+    assert_eq!(find("/"), Some(("/", vec![])));
+    assert_eq!(find("/a/b"), Some(("/", vec![])));
+    assert_eq!(find("/doc/a"), Some(("/doc/", vec![])));
+    assert_eq!(find("/doc/readme.md"), Some(("/doc/readme.md", vec![])));
+    assert_eq!(find("/doc/readme.md/a"), Some(("/doc/", vec![])));
+    assert_eq!(
+        find("/builds/dev/latest/a.tgz"),
+        Some(("/builds/{branch}/latest/", caps(&[("branch", "dev")])))
+    );
+    assert_eq!(find("/builds/master/latest"), Some(("/builds/master/latest/", vec![])));
+    assert_eq!(find("/builds/dev/oldest"), Some(("/", vec![])));
+    assert_eq!(
+        find("/home/~biluohc/"),
+        Some(("/home/~{user}/", caps(&[("user", "biluohc")])))
+    );
+    assert_eq!(find("/home/biluohc/"), Some(("/", vec![])));
+    assert_eq!(find("/issues/123"), Some(("/issues/{id:\\d{1,5}}", caps(&[("id", "123")]))));
+    assert_eq!(find("/issues/123456"), Some(("/", vec![])));
+    assert_eq!(find("/src/lib.rs"), Some(("/src/*.rs", vec![])));
+    assert_eq!(find("/src/lib.rs.bak"), Some(("/", vec![])));
+
+    assert!(Matcher::new().insert("/a/{b", 0).is_err());
+    assert!(Matcher::new().insert("/a/{}", 0).is_err());
+    assert!(Matcher::new().insert("/a/{b}-{b}", 0).is_err());
+    assert!(Matcher::new().insert("/a/{b:(}", 0).is_err());
+    assert!(Matcher::new().find("/").is_none());
+
+    assert_eq!(capture_names("/{a}/x/{b:\\d+}-{c}/").unwrap(), vec!["a", "b", "c"]);
+    assert_eq!(
+        expand("/home/{user}/public_html", &caps(&[("user", "biluohc")])),
+        "/home/biluohc/public_html"
+    );
+}
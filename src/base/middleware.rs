@@ -1,3 +1,5 @@
+use futures::future::{ready, BoxFuture, FutureExt};
+
 use std::{net::SocketAddr, ops::Index};
 
 use crate::{
@@ -5,6 +7,7 @@ use crate::{
     base::{Request, Response},
 };
 
+/// The middleware without I/O, every `MiddleWare` is an `AsyncMiddleWare`
 pub trait MiddleWare {
     fn before(&self, _req: &Request, _addr: &SocketAddr, _ctx: &mut Ctx) -> Result<(), Response> {
         Ok(())
@@ -12,7 +15,32 @@ pub trait MiddleWare {
     fn after(&self, _resp: &mut Response, _addr: &SocketAddr, _ctx: &mut Ctx) {}
 }
 
-pub type BoxedMiddleWare = Box<dyn MiddleWare + Send + Sync + 'static>;
+/// The middleware can await I/O, the `Err` of `before` stops the rest and its response goes through the `after`s of the previous ones
+pub trait AsyncMiddleWare {
+    fn before<'a>(
+        &'a self,
+        _req: &'a Request,
+        _addr: &'a SocketAddr,
+        _ctx: &'a mut Ctx,
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        ready(Ok(())).boxed()
+    }
+    fn after<'a>(&'a self, _resp: &'a mut Response, _addr: &'a SocketAddr, _ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        ready(()).boxed()
+    }
+}
+
+impl<M: MiddleWare> AsyncMiddleWare for M {
+    fn before<'a>(&'a self, req: &'a Request, addr: &'a SocketAddr, ctx: &'a mut Ctx) -> BoxFuture<'a, Result<(), Response>> {
+        ready(MiddleWare::before(self, req, addr, ctx)).boxed()
+    }
+    fn after<'a>(&'a self, resp: &'a mut Response, addr: &'a SocketAddr, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        MiddleWare::after(self, resp, addr, ctx);
+        ready(()).boxed()
+    }
+}
+
+pub type BoxedMiddleWare = Box<dyn AsyncMiddleWare + Send + Sync + 'static>;
 
 #[derive(Default)]
 pub struct MiddleWares(Vec<BoxedMiddleWare>);
@@ -30,14 +58,14 @@ impl MiddleWares {
     }
     pub fn push<M>(&mut self, middleware: M)
     where
-        M: MiddleWare + Send + Sync + 'static,
+        M: AsyncMiddleWare + Send + Sync + 'static,
     {
         self.0.push(Box::new(middleware) as _)
     }
 }
 
 impl Index<usize> for MiddleWares {
-    type Output = dyn MiddleWare;
+    type Output = dyn AsyncMiddleWare + Send + Sync;
 
     fn index(&self, idx: usize) -> &Self::Output {
         &*self.0[idx]
@@ -49,3 +77,26 @@ impl AsRef<[BoxedMiddleWare]> for MiddleWares {
         self.0.as_ref()
     }
 }
+
+#[test]
+fn middleware_test() {
+    use futures::executor::block_on;
+
+    struct Refuser;
+    impl MiddleWare for Refuser {
+        fn before(&self, _req: &Request, _addr: &SocketAddr, _ctx: &mut Ctx) -> Result<(), Response> {
+            Err(Response::new("refused".into()))
+        }
+        fn after(&self, resp: &mut Response, _addr: &SocketAddr, _ctx: &mut Ctx) {
+            *resp.status_mut() = hyper::StatusCode::FORBIDDEN;
+        }
+    }
+
+    let mut middlewares = MiddleWares::with_capacity(1);
+    middlewares.push(Refuser);
+    let (req, addr, mut ctx) = (Request::default(), ([127, 0, 0, 1], 0).into(), Ctx::new());
+
+    let mut resp = block_on(middlewares[0].before(&req, &addr, &mut ctx)).unwrap_err();
+    block_on(middlewares[0].after(&mut resp, &addr, &mut ctx));
+    assert_eq!(resp.status(), 403);
+}
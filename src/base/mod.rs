@@ -1,9 +1,14 @@
 pub mod ctx;
+pub mod exclude;
+pub mod forwarded;
 pub mod handler;
+pub mod matcher;
 pub mod middleware;
 mod router;
+pub mod sendfile;
 mod server;
 mod service;
+pub mod vhost;
 
 pub use router::Router;
 pub use server::Server;
@@ -21,6 +26,18 @@ pub fn response() -> ResponseBuilder {
     ResponseBuilder::new()
 }
 
+/// The address of server, stored in `Request::extensions` for the views
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerAddr(pub std::net::SocketAddr);
+
+/// The address of peer, the client address may be resolved from the headers of trusted proxies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerAddr {
+    pub addr: std::net::SocketAddr,
+    // by the `trustedProxies` of the router serving the request
+    pub trusted: bool,
+}
+
 use hyper::{
     header::{AsHeaderName, HeaderValue},
     HeaderMap,
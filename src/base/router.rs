@@ -1,80 +1,216 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use crate::{
     base::{
         ctx::{ctxs, Ctx},
+        forwarded::TrustedProxies,
         handler::BoxedHandler,
         http,
+        matcher::{capture_names, expand, Captures, Matcher},
         middleware::MiddleWares,
-        Request, Response,
+        vhost::{request_host, VHost},
+        PeerAddr, Request, Response, ServerAddr,
     },
     config::{Config, Route},
-    handlers::{fs_handler, method_maybe_proxy, notfound_handler, proxy_handler},
-    middlewares::{auth::Authenticator, cors::CorsController, logger::Logger, path::PathNormalizer},
-    service::GlobalState,
+    handlers::{fs_handler, method_maybe_proxy, notfound_handler, proxy_handler, upstream_handler},
+    middlewares::{
+        auth::Authenticator, cors::CorsController, error_pages::ErrorPages, headers::HeadersSetter, logger::Logger,
+        methods::MethodsFilter, path::PathNormalizer, rewrite::Rewriter,
+    },
+    service::SharedState,
 };
 
+type RouteEntry = (Arc<Route>, MiddleWares, BoxedHandler);
+
+struct Routes {
+    entries: Vec<RouteEntry>,
+    matcher: Matcher,
+}
+
+impl Routes {
+    fn new<'a>(routes: impl Iterator<Item = &'a Route>, config: &Config) -> crate::how::Result<Self> {
+        let mut entries = vec![];
+        let mut matcher = Matcher::new();
+
+        for r in routes {
+            let mut r = r.clone();
+            r.urlcs = r.url.split('/').filter(|c| !c.is_empty()).count();
+
+            let names = capture_names(&r.url)?;
+            if let Some(name) = unknown_capture(r.target(), &names) {
+                return Err(format_err!(
+                    "route({:?})'s target({:?}) use unknown capture {:?}",
+                    r.url,
+                    r.target(),
+                    name
+                ));
+            }
+            matcher.insert(&r.url, entries.len())?;
+
+            let mut middlewares = MiddleWares::new();
+            // the first, its after also runs if the others refuse
+            let headers = HeadersSetter::for_route(&config.headers, &r.headers)?;
+            if !headers.is_empty() {
+                middlewares.push(headers);
+            }
+            let error_pages = ErrorPages::for_route(&config.error_pages, &r.error_pages)?;
+            if !error_pages.is_empty() {
+                middlewares.push(error_pages);
+            }
+            // before Authenticator, the preflight has no credentials
+            if let Some(methods) = MethodsFilter::for_route(&r)? {
+                middlewares.push(methods);
+            }
+            if let Some(auth) = &config.auth {
+                if r.authorized {
+                    let authenticator = Authenticator::new(auth.clone());
+                    middlewares.push(authenticator);
+                }
+            }
+
+            let handler = match &r.upstream {
+                Some(upstream) => upstream_handler(upstream)?,
+                None => fs_handler(&r)?,
+            };
+
+            entries.push((Arc::new(r), middlewares, handler));
+        }
+
+        Ok(Routes { entries, matcher })
+    }
+
+    fn find(&self, reqpath: &str) -> Option<(&RouteEntry, Captures)> {
+        self.matcher
+            .find(reqpath)
+            .map(|(idx, captures)| (&self.entries[idx], captures))
+    }
+}
+
+// the `{name}` of path not in the captures of url
+fn unknown_capture<'a>(path: &'a str, names: &[String]) -> Option<&'a str> {
+    path.split('{')
+        .skip(1)
+        .filter_map(|s| s.find('}').map(|idx| &s[..idx]))
+        .find(|name| !names.iter().any(|n| n == name))
+}
+
+// substitute the captures into the route's url and path
+fn route_captured(route: &Route, reqpath: &str, captures: &[(String, String)]) -> Route {
+    let mut route = route.clone();
+
+    let mut url = String::with_capacity(reqpath.len());
+    for c in reqpath.split('/').filter(|c| !c.is_empty()).take(route.urlcs) {
+        url.push('/');
+        url.push_str(c);
+    }
+    if route.url.ends_with('/') || url.is_empty() {
+        url.push('/');
+    }
+
+    route.path = expand(&route.path, captures);
+    route.upstream = route.upstream.map(|upstream| expand(&upstream, captures));
+    route.url = url;
+    route
+}
+
+// replace the query of request, the path is kept as the handlers use the `ReqPath` of `Ctx`
+fn set_query(req: &mut Request, query: Option<&str>) {
+    let path = req.uri().path();
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    let uri = path_and_query.parse().map_err(http::Error::from).and_then(|pq| {
+        parts.path_and_query = Some(pq);
+        http::Uri::from_parts(parts).map_err(http::Error::from)
+    });
+    match uri {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(e) => warn!("rewrited query {:?} invalid: {}", query, e),
+    }
+}
+
+#[test]
+fn set_query_test() {
+    let mut req = hyper::Request::get("http://example.com/docs/a?x=1")
+        .body(Default::default())
+        .unwrap();
+    set_query(&mut req, Some("lang=en&x=1"));
+    assert_eq!(req.uri(), "http://example.com/docs/a?lang=en&x=1");
+    set_query(&mut req, None);
+    assert_eq!(req.uri(), "http://example.com/docs/a");
+}
+
 pub struct Router {
     global_middlewares: MiddleWares,
-    routes: Vec<(Route, MiddleWares, BoxedHandler)>,
+    routes: Routes,
+    vhosts: Vec<(VHost, Routes)>,
     notfound: BoxedHandler,
-    proxy: Option<(Route, MiddleWares, BoxedHandler)>,
+    proxy: Option<RouteEntry>,
+    trusted_proxies: TrustedProxies,
 }
 
 impl Router {
     pub fn new(config: &Config) -> crate::how::Result<Self> {
-        let mut routes = config
-            .routes
-            .values()
-            .map(|r| {
-                let mut r = r.clone();
-                r.urlcs = r.url.split('/').filter(|c| !c.is_empty()).count();
-
-                let mut middlewares = MiddleWares::new();
-                if let Some(auth) = &config.auth {
-                    if r.authorized {
-                        let authenticator = Authenticator::new(auth.clone());
-                        middlewares.push(authenticator);
-                    }
-                }
+        let routes = Routes::new(config.routes.values(), config)?;
 
-                (r, middlewares, fs_handler())
-            })
-            .collect::<Vec<(Route, _, _)>>();
+        let mut vhosts = config
+            .vhosts
+            .iter()
+            .map(|(host, routes)| Ok((host.parse()?, Routes::new(routes.values(), config)?)))
+            .collect::<crate::how::Result<Vec<(VHost, _)>>>()?;
 
-        routes.sort_by(|a, b| b.0.urlcs.cmp(&a.0.urlcs));
+        vhosts.sort_by_key(|(vhost, _)| std::cmp::Reverse(vhost.priority()));
 
-        let mut global_middlewares = MiddleWares::with_capacity(3);
+        let mut global_middlewares = MiddleWares::with_capacity(6);
         global_middlewares.push(Logger);
+        // for the requests not matched any route
+        global_middlewares.push(HeadersSetter::global(&config.headers)?);
+        global_middlewares.push(ErrorPages::global(&config.error_pages)?);
         global_middlewares.push(PathNormalizer);
+        // after PathNormalizer, the rules only see the normalized path
+        global_middlewares.push(Rewriter::new(&config.rewrites)?);
         global_middlewares.push(CorsController::new(&config.cors)?);
 
+        let proxy = if let Some(route) = config.proxy.as_ref() {
+            let mut middlewares = MiddleWares::new();
+            let headers = HeadersSetter::new(&config.headers)?;
+            if !headers.is_empty() {
+                middlewares.push(headers);
+            }
+            if let Some(auth) = &config.auth {
+                if route.authorized {
+                    let authenticator = Authenticator::new(auth.clone());
+                    middlewares.push(authenticator);
+                }
+            }
+
+            Some((Arc::new(route.clone()), middlewares, proxy_handler(&route.path)?))
+        } else {
+            None
+        };
+
         Ok(Self {
+            proxy,
             routes,
+            vhosts,
             global_middlewares,
             notfound: notfound_handler(),
-            proxy: config.proxy.as_ref().map(|route| {
-                (
-                    route.clone(),
-                    {
-                        let mut middlewares = MiddleWares::new();
-                        if let Some(auth) = &config.auth {
-                            if route.authorized {
-                                let authenticator = Authenticator::new(auth.clone());
-                                middlewares.push(authenticator);
-                            }
-                        }
-
-                        middlewares
-                    },
-                    proxy_handler(&route.path),
-                )
-            }),
+            trusted_proxies: TrustedProxies::new(&config.trusted_proxies),
         })
     }
 
-    pub async fn call(addr: SocketAddr, req: Request, state: GlobalState) -> Result<Response, http::Error> {
+    pub async fn call(addr: SocketAddr, mut req: Request, state: SharedState) -> Result<Response, http::Error> {
+        // the old router lives until its requests finish after a reload
         let this = state.router();
+        req.extensions_mut().insert(PeerAddr {
+            addr,
+            trusted: this.trusted_proxies.is_trusted(&addr.ip()),
+        });
+        let addr = this.trusted_proxies.client_addr(&req, addr);
+        req.extensions_mut().insert(ServerAddr(state.config().addr));
 
         let mut ctx = Ctx::with_capacity(ctxs::CAPACITY);
         ctx.insert(state);
@@ -85,44 +221,57 @@ impl Router {
         }
 
         for idx in 0..this.global_middlewares.len() {
-            if let Err(mut resp) = (this.global_middlewares[idx]).before(&req, &addr, &mut ctx) {
+            if let Err(mut resp) = (this.global_middlewares[idx]).before(&req, &addr, &mut ctx).await {
                 // take global_middlewares return ok
                 for gm in this.global_middlewares.as_ref()[0..idx].iter().rev() {
-                    gm.after(&mut resp, &addr, &mut ctx);
+                    gm.after(&mut resp, &addr, &mut ctx).await;
                 }
 
                 return Ok(resp);
             }
         }
 
+        if let Some(query) = ctx.remove::<ctxs::ReqQuery>() {
+            set_query(&mut req, query.as_deref());
+        }
+
         let reqpath = ctx.get::<ctxs::ReqPath>().unwrap();
         let matched = if maybe_is_proxy {
-            this.proxy.as_ref()
+            this.proxy.as_ref().map(|entry| (entry, vec![]))
         } else {
-            this.routes.iter().find(|&(route, _, _)| {
-                reqpath.starts_with(&route.url) && (route.url.ends_with('/') || reqpath.len() == route.url.len())
-                    || reqpath.trim_end_matches('/') == route.url.trim_end_matches('/')
-            })
+            // host first, the default vhost is the fallback
+            let host = request_host(&req);
+            this.vhosts
+                .iter()
+                .find(|(vhost, _)| vhost.is_match(host))
+                .map(|(_, routes)| routes)
+                .unwrap_or(&this.routes)
+                .find(reqpath)
         };
 
         debug!(
             "matched: {} -> {:?}",
             reqpath,
-            matched.as_ref().map(|m| m.0.url.as_str()) // .unwrap_or("")
+            matched.as_ref().map(|m| (m.0).0.url.as_str()) // .unwrap_or("")
         );
 
-        let mut resp = if let Some((route, middlewares, handler)) = matched {
-            ctx.insert(route);
+        let mut resp = if let Some(((route, middlewares, handler), captures)) = matched {
+            if captures.is_empty() {
+                ctx.insert(route.clone());
+            } else {
+                ctx.insert(Arc::new(route_captured(route, reqpath, &captures)));
+            }
+            ctx.insert(captures);
             // assert_eq!(route, *ctx.get::<ctx::Route>().unwrap());
 
+            // the middlewares before the refused one, or all
+            let mut passed = middlewares.len();
             let mut resp = None;
             for idx in 0..middlewares.len() {
-                if let Err(mut resp_) = (middlewares[idx]).before(&req, &addr, &mut ctx) {
-                    // take middlewares return ok
-                    for lm in middlewares.as_ref()[0..idx].iter().rev() {
-                        lm.after(&mut resp_, &addr, &mut ctx);
-                    }
+                if let Err(resp_) = (middlewares[idx]).before(&req, &addr, &mut ctx).await {
+                    passed = idx;
                     resp = Some(resp_);
+                    break;
                 }
             }
 
@@ -132,8 +281,9 @@ impl Router {
                 (*handler)(req, &addr, &mut ctx).await?
             };
 
-            for lm in middlewares.as_ref().iter().rev() {
-                lm.after(&mut resp, &addr, &mut ctx);
+            // take middlewares return ok
+            for lm in middlewares.as_ref()[0..passed].iter().rev() {
+                lm.after(&mut resp, &addr, &mut ctx).await;
             }
 
             resp
@@ -142,7 +292,7 @@ impl Router {
         };
 
         for gm in this.global_middlewares.as_ref().iter().rev() {
-            gm.after(&mut resp, &addr, &mut ctx);
+            gm.after(&mut resp, &addr, &mut ctx).await;
         }
 
         Ok(resp)
@@ -0,0 +1,287 @@
+use bytes::{Buf, Bytes};
+use futures::stream::{self, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, IoSlice},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+#[cfg(target_os = "linux")]
+pub use self::linux::SendfileStream;
+
+/// The size of a placeholder chunk
+pub const CHUNK_SIZE: usize = 1024 * 256;
+// the max of iovecs passed to writev
+const IOVECS: usize = 64;
+
+// the chunks of body point into it are replaced by the `sendfile` of the queued files
+static PLACEHOLDER: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+
+fn is_placeholder(chunk: &[u8]) -> bool {
+    let base = PLACEHOLDER.as_ptr() as usize;
+    let ptr = chunk.as_ptr() as usize;
+    !chunk.is_empty() && ptr >= base && ptr + chunk.len() <= base + CHUNK_SIZE
+}
+
+/// The IO without `sendfile`, the placeholder chunks reach it are a bug of the callers
+#[derive(Debug)]
+pub struct NoSendfile<IO>(pub IO);
+
+impl<IO> NoSendfile<IO> {
+    fn assert_no_placeholder(slices: &[IoSlice<'_>]) {
+        debug_assert!(
+            !slices.iter().any(|s| is_placeholder(s)),
+            "the placeholder chunk reaches the IO without sendfile"
+        );
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for NoSendfile<IO> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for NoSendfile<IO> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Self::assert_no_placeholder(&[IoSlice::new(buf)]);
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_write_buf<B: Buf>(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut B) -> Poll<io::Result<usize>> {
+        if cfg!(debug_assertions) {
+            let mut slices = [IoSlice::new(&[]); IOVECS];
+            let count = buf.bytes_vectored(&mut slices);
+            Self::assert_no_placeholder(&slices[..count]);
+        }
+        Pin::new(&mut self.0).poll_write_buf(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    file: Arc<File>,
+    offset: u64,
+    len: usize,
+}
+
+/// The files to send of a HTTP/1 connection, in the order of the placeholder chunks, stored in `Request::extensions`
+#[derive(Debug, Default, Clone)]
+pub struct SendfileQueue(Arc<Mutex<VecDeque<Job>>>);
+
+impl SendfileQueue {
+    /// The body of `[start, end)` of file, its chunks queue the jobs when polled
+    pub fn body(&self, file: File, start: u64, end: u64) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let this = self.clone();
+        let file = Arc::new(file);
+        let mut offset = start;
+
+        stream::iter(std::iter::from_fn(move || {
+            if offset >= end {
+                return None;
+            }
+            let len = CHUNK_SIZE.min((end - offset) as usize);
+            this.0.lock().unwrap().push_back(Job {
+                file: file.clone(),
+                offset,
+                len,
+            });
+            offset += len as u64;
+            Some(Ok(Bytes::from_static(&PLACEHOLDER[..len])))
+        }))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use bytes::Buf;
+    use mio::net::TcpStream as MioTcpStream;
+    use tokio::{
+        io::{AsyncRead, AsyncWrite, PollEvented},
+        net::TcpStream,
+    };
+
+    use std::{
+        io::{self, IoSlice},
+        net::{Shutdown, TcpStream as StdTcpStream},
+        os::unix::io::{AsRawFd, FromRawFd},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use super::{is_placeholder, SendfileQueue, IOVECS};
+
+    /// The plain TCP stream sends the placeholder chunks by `sendfile`, others by `writev`
+    pub struct SendfileStream {
+        io: PollEvented<MioTcpStream>,
+        queue: SendfileQueue,
+    }
+
+    impl SendfileStream {
+        pub fn new(socket: TcpStream) -> io::Result<(Self, SendfileQueue)> {
+            // the tokio's one deregisters itself on drop
+            let fd = unsafe { libc::dup(socket.as_raw_fd()) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            drop(socket);
+
+            let socket = MioTcpStream::from_stream(unsafe { StdTcpStream::from_raw_fd(fd) })?;
+            let queue = SendfileQueue::default();
+            let this = Self {
+                io: PollEvented::new(socket)?,
+                queue: queue.clone(),
+            };
+            Ok((this, queue))
+        }
+
+        fn poll_op<F>(&mut self, cx: &mut Context<'_>, mut op: F) -> Poll<io::Result<usize>>
+        where
+            F: FnMut(&mut Self) -> io::Result<usize>,
+        {
+            futures::ready!(self.io.poll_write_ready(cx))?;
+            match op(self) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_write_ready(cx)?;
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            }
+        }
+
+        fn writev(&self, slices: &[IoSlice<'_>]) -> io::Result<usize> {
+            let rc = unsafe {
+                libc::writev(
+                    self.io.get_ref().as_raw_fd(),
+                    slices.as_ptr() as *const libc::iovec,
+                    slices.len() as _,
+                )
+            };
+            if rc < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(rc as usize)
+            }
+        }
+
+        fn sendfile(&self, len: usize) -> io::Result<usize> {
+            let mut queue = self.queue.0.lock().unwrap();
+            let job = match queue.front_mut() {
+                Some(job) if job.len >= len => job,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the placeholder chunk without sendfile job",
+                    ))
+                }
+            };
+
+            let mut offset = job.offset as libc::off_t;
+            let rc = unsafe { libc::sendfile(self.io.get_ref().as_raw_fd(), job.file.as_raw_fd(), &mut offset, len) };
+            match rc {
+                rc if rc < 0 => return Err(io::Error::last_os_error()),
+                0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file is truncated")),
+                _ => {}
+            }
+
+            let sent = rc as usize;
+            job.offset += sent as u64;
+            job.len -= sent;
+            if job.len == 0 {
+                queue.pop_front();
+            }
+            Ok(sent)
+        }
+    }
+
+    impl AsyncRead for SendfileStream {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.io).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for SendfileStream {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if is_placeholder(buf) {
+                this.poll_op(cx, |this| this.sendfile(buf.len()))
+            } else {
+                this.poll_op(cx, |this| this.writev(&[IoSlice::new(buf)]))
+            }
+        }
+
+        // the hyper keeps the chunks of body in place if the vectored ones used
+        fn poll_write_buf<B: Buf>(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut B) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if !buf.has_remaining() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let mut slices = [IoSlice::new(&[]); IOVECS];
+            let count = buf.bytes_vectored(&mut slices);
+            let written = if is_placeholder(&slices[0]) {
+                let len = slices[0].len();
+                futures::ready!(this.poll_op(cx, |this| this.sendfile(len)))?
+            } else {
+                let count = slices[..count].iter().take_while(|s| !is_placeholder(s)).count();
+                futures::ready!(this.poll_op(cx, |this| this.writev(&slices[..count])))?
+            };
+
+            buf.advance(written);
+            Poll::Ready(Ok(written))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(self.io.get_ref().shutdown(Shutdown::Write))
+        }
+    }
+}
+
+#[test]
+fn sendfile_queue_test() {
+    use futures::{executor::block_on, StreamExt};
+
+    let queue = SendfileQueue::default();
+    let file = File::open(file!()).unwrap();
+    let len = CHUNK_SIZE as u64 + 10;
+    let chunks = block_on(queue.body(file, 5, 5 + len).collect::<Vec<_>>());
+
+    let lens = chunks.iter().map(|c| c.as_ref().unwrap().len()).collect::<Vec<_>>();
+    assert_eq!(lens, vec![CHUNK_SIZE, 10]);
+    assert!(chunks.iter().all(|c| is_placeholder(c.as_ref().unwrap())));
+    assert!(!is_placeholder(b"fht2p"));
+    assert!(!is_placeholder(&PLACEHOLDER[..0]));
+
+    let jobs = queue.0.lock().unwrap();
+    let jobs = jobs.iter().map(|j| (j.offset, j.len)).collect::<Vec<_>>();
+    assert_eq!(jobs, vec![(5, CHUNK_SIZE), (5 + CHUNK_SIZE as u64, 10)]);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "the placeholder chunk reaches the IO without sendfile")]
+fn no_sendfile_test() {
+    use futures::executor::block_on;
+    use tokio::io::AsyncWriteExt;
+
+    let mut io = NoSendfile(tokio::io::sink());
+    block_on(io.write_all(b"fht2p")).unwrap();
+    block_on(io.write_all(&PLACEHOLDER[..5])).unwrap();
+}
@@ -1,4 +1,7 @@
-use futures::FutureExt;
+use futures::{
+    future::{select, Either},
+    Future, FutureExt,
+};
 use tokio::{
     net::{TcpListener, TcpStream},
     time::{delay_for, timeout},
@@ -6,22 +9,36 @@ use tokio::{
 
 use std::{io, net::SocketAddr, time::Duration};
 
+use super::sendfile::NoSendfile;
+#[cfg(target_os = "linux")]
+use super::sendfile::SendfileStream;
 use crate::{
     base::Service,
     how::{Error, Result},
-    service::GlobalState,
+    service::SharedState,
 };
 
 pub struct Server;
 
 impl Server {
-    pub async fn run(state: GlobalState) -> Result<()> {
-        let mut tcp = TcpListener::bind(state.config().addr).await?;
+    pub async fn run<F>(mut tcp: TcpListener, state: SharedState, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()> + Unpin,
+    {
+        let mut shutdown = shutdown;
 
         loop {
-            match tcp.accept().await.and_then(|(s, sa)| s.set_nodelay(true).map(|_| (s, sa))) {
+            let accepted = match select(tcp.accept().boxed(), &mut shutdown).await {
+                Either::Left((accepted, _)) => accepted,
+                Either::Right(((), _)) => {
+                    info!("{} stop accepting", state.config().addr);
+                    return Ok(());
+                }
+            };
+
+            match accepted.and_then(|(s, sa)| s.set_nodelay(true).map(|_| (s, sa))) {
                 Ok((socket, addr)) => {
-                    state.spawn(serve_socket(socket, addr, state).map(move |rest| {
+                    tokio::spawn(serve_socket(socket, addr, state.clone()).map(move |rest| {
                         if let Err(e) = rest {
                             error!("socket {}: {}", addr, e);
                         }
@@ -41,17 +58,34 @@ impl Server {
     }
 }
 
-pub async fn serve_socket(socket: TcpStream, addr: SocketAddr, state: GlobalState) -> Result<()> {
-    let service = Service::new(addr, state);
+pub async fn serve_socket(socket: TcpStream, addr: SocketAddr, state: SharedState) -> Result<()> {
+    let service = Service::new(addr, state.clone());
 
     if let Some(tls) = state.tls() {
         let socket = timeout(Duration::from_secs(10), tls.accept(socket))
             .await
             .map_err(|_| format_err!("tls handshake timeout"))
             .and_then(|res| res.map_err(Error::from))?;
-        state.http().serve_connection(socket, service).with_upgrades().await?;
+        state
+            .http()
+            .serve_connection(NoSendfile(socket), service)
+            .with_upgrades()
+            .await?;
     } else {
-        state.http().serve_connection(socket, service).with_upgrades().await?;
+        #[cfg(target_os = "linux")]
+        {
+            if state.config().sendfile {
+                let (socket, queue) = SendfileStream::new(socket)?;
+                let service = service.sendfile(queue);
+                state.http().serve_connection(socket, service).with_upgrades().await?;
+                return Ok(());
+            }
+        }
+        state
+            .http()
+            .serve_connection(NoSendfile(socket), service)
+            .with_upgrades()
+            .await?;
     }
 
     Ok(())
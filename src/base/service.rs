@@ -7,19 +7,28 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use crate::{
-    base::{http, Request, Response, Router},
-    consts::HSTS_HEADER,
-    service::GlobalState,
+    base::{http, sendfile::SendfileQueue, Request, Response, Router},
+    service::SharedState,
 };
 
 pub struct Service {
     pub(crate) peer_addr: SocketAddr,
-    pub(crate) state: GlobalState,
+    pub(crate) state: SharedState,
+    pub(crate) sendfile: Option<SendfileQueue>,
 }
 
 impl Service {
-    pub fn new(peer_addr: SocketAddr, state: GlobalState) -> Self {
-        Self { peer_addr, state }
+    pub fn new(peer_addr: SocketAddr, state: SharedState) -> Self {
+        Self {
+            peer_addr,
+            state,
+            sendfile: None,
+        }
+    }
+    /// The connection supports the `sendfile`
+    pub fn sendfile(mut self, queue: SendfileQueue) -> Self {
+        self.sendfile = Some(queue);
+        self
     }
 }
 
@@ -33,21 +42,29 @@ impl TowerService<Request> for Service {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request) -> Self::Future {
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        if let Some(queue) = self.sendfile.as_ref() {
+            req.extensions_mut().insert(queue.clone());
+        }
+
         let keepalive = if self.state.config().keep_alive {
             "Keep-Alive"
         } else {
             "Close"
         };
 
-        Router::call(self.peer_addr, req, self.state)
+        let state = self.state.clone();
+
+        Router::call(self.peer_addr, req, self.state.clone())
             .map(move |resp| {
                 resp.map(|mut resp| {
                     let headers = resp.headers_mut();
 
                     headers.insert(CONNECTION, HeaderValue::from_static(keepalive));
-                    if let Some(hsts) = HSTS_HEADER.get() {
-                        headers.insert(STRICT_TRANSPORT_SECURITY, HeaderValue::from_static(hsts.as_str()));
+                    if let Some(hsts) = state.config().hsts_header.as_ref() {
+                        if let Ok(hsts) = HeaderValue::from_str(hsts) {
+                            headers.insert(STRICT_TRANSPORT_SECURITY, hsts);
+                        }
                     }
 
                     resp
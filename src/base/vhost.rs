@@ -0,0 +1,110 @@
+use hyper::header;
+
+use std::str::FromStr;
+
+use crate::{
+    base::{HeaderGetStr, Request},
+    how::{Error, Result},
+};
+
+/// `example.com` or `*.example.com`(all subdomains, but not `example.com` itself)
+#[derive(Debug, Clone, PartialEq)]
+pub enum VHost {
+    Exact(String),
+    // .example.com
+    Wildcard(String),
+}
+
+impl VHost {
+    pub fn is_match(&self, host: &str) -> bool {
+        match self {
+            VHost::Exact(name) => name.eq_ignore_ascii_case(host),
+            VHost::Wildcard(suffix) => {
+                let (host, suffix) = (host.as_bytes(), suffix.as_bytes());
+                host.len() > suffix.len() && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+        }
+    }
+    /// exact first, then the longer wildcard
+    pub fn priority(&self) -> (bool, usize) {
+        match self {
+            VHost::Exact(name) => (true, name.len()),
+            VHost::Wildcard(suffix) => (false, suffix.len()),
+        }
+    }
+}
+
+impl FromStr for VHost {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let name = s.trim_start_matches("*.");
+
+        if name.is_empty() || name.contains(|c: char| c == '*' || c == '/' || c.is_whitespace()) {
+            return Err(format_err!("invalid vhost: {:?}", s));
+        }
+
+        Ok(if s.starts_with("*.") {
+            VHost::Wildcard(s[1..].to_owned())
+        } else {
+            VHost::Exact(s)
+        })
+    }
+}
+
+/// The host of request without port, from `Host` header or the uri(absolute-form)
+pub fn request_host(req: &Request) -> &str {
+    let host = req
+        .headers()
+        .get_str_option(header::HOST)
+        .or_else(|| req.uri().authority().map(|a| a.as_str()))
+        .unwrap_or_default();
+
+    if host.starts_with('[') {
+        host.find(']').map(|idx| &host[..=idx]).unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or_default()
+    }
+}
+
+#[test]
+fn vhost_test() {
+    assert!("".parse::<VHost>().is_err());
+    assert!("*.".parse::<VHost>().is_err());
+    assert!("a.*.com".parse::<VHost>().is_err());
+    assert!("a.com/".parse::<VHost>().is_err());
+
+    let exact = "Example.com".parse::<VHost>().unwrap();
+    assert_eq!(exact, VHost::Exact("example.com".to_owned()));
+    assert!(exact.is_match("example.COM"));
+    assert!(!exact.is_match("a.example.com"));
+
+    let wildcard = "*.example.com".parse::<VHost>().unwrap();
+    assert_eq!(wildcard, VHost::Wildcard(".example.com".to_owned()));
+    assert!(wildcard.is_match("a.example.com"));
+    assert!(wildcard.is_match("a.b.Example.com"));
+    assert!(!wildcard.is_match("example.com"));
+    assert!(!wildcard.is_match("aexample.com"));
+    assert!(exact.priority() > wildcard.priority());
+}
+
+#[test]
+fn request_host_test() {
+    let req = |host: &str| {
+        hyper::Request::get("/")
+            .header("host", host)
+            .body(Default::default())
+            .unwrap()
+    };
+
+    assert_eq!(request_host(&req("example.com")), "example.com");
+    assert_eq!(request_host(&req("example.com:8000")), "example.com");
+    assert_eq!(request_host(&req("[::1]:8000")), "[::1]");
+    assert_eq!(request_host(&req("[::1]")), "[::1]");
+
+    let req = hyper::Request::get("http://example.com:8000/")
+        .body(Default::default())
+        .unwrap();
+    assert_eq!(request_host(&req), "example.com");
+}
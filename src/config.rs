@@ -1,7 +1,15 @@
 use tokio_rustls::rustls::{self, internal::pemfile};
 pub use tokio_rustls::TlsAcceptor;
 
-use std::{collections::BTreeMap as Map, fs, io, io::Seek, net::SocketAddr, str::FromStr, sync::Arc};
+use std::{
+    collections::BTreeMap as Map,
+    convert::TryFrom,
+    fs, io,
+    io::Seek,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
 
 use crate::{
     args::Server,
@@ -122,6 +130,89 @@ impl Into<Route> for ProxyRoute {
     }
 }
 
+/// `ip` or `ip/prefix`, such as `127.0.0.1`, `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // the peer of a dual-stack listener is `::ffff:a.b.c.d`
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            v4 => *v4,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, prefix) = match s.find('/') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|e| format_err!("invalid ip({}): {}", addr, e))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format_err!("invalid prefix({}) for {}", p, addr))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+#[test]
+fn cidr_test() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    assert!("".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/".parse::<Cidr>().is_err());
+    assert!("::1/129".parse::<Cidr>().is_err());
+
+    let c = "127.0.0.1".parse::<Cidr>().unwrap();
+    assert!(c.contains(&ip("127.0.0.1")));
+    assert!(c.contains(&ip("::ffff:127.0.0.1")));
+    assert!(!c.contains(&ip("127.0.0.2")));
+
+    let c = "10.0.0.0/8".parse::<Cidr>().unwrap();
+    assert!(c.contains(&ip("10.1.2.3")));
+    assert!(!c.contains(&ip("11.1.2.3")));
+    assert!(!c.contains(&ip("::1")));
+
+    let c = "0.0.0.0/0".parse::<Cidr>().unwrap();
+    assert!(c.contains(&ip("192.168.1.1")));
+
+    let c = "fd00::/8".parse::<Cidr>().unwrap();
+    assert!(c.contains(&ip("fd12::1")));
+    assert!(!c.contains(&ip("fe80::1")));
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CorsConfig {
@@ -131,6 +222,145 @@ pub struct CorsConfig {
     pub allow_origins: Option<String>,
 }
 
+/// `{ "maxSize": 8388608, "maxFileSize": 65536 }`, the files(<= maxFileSize) kept in memory until their total exceeds maxSize
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileCacheConfig {
+    #[serde(default = "FileCacheConfig::max_size")]
+    pub max_size: u64,
+    #[serde(default = "FileCacheConfig::max_file_size")]
+    pub max_file_size: u64,
+}
+
+impl FileCacheConfig {
+    fn max_size() -> u64 {
+        FILE_CACHE_SIZE
+    }
+    fn max_file_size() -> u64 {
+        FILE_CACHE_FILE_SIZE
+    }
+}
+
+impl Default for FileCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size: FILE_CACHE_SIZE,
+            max_file_size: FILE_CACHE_FILE_SIZE,
+        }
+    }
+}
+
+/// `{ "name": "X-Robots-Tag", "value": "noindex" }`
+/// or `{ "name": "Cache-Control", "value": "max-age=31536000, immutable", "files": "*.min.{js,css}", "status": [200] }`,
+/// the later one of the same name wins if both matched
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HeaderRule {
+    pub name: String,
+    pub value: String,
+    // any if empty
+    #[serde(default)]
+    pub status: Vec<u16>,
+    // the glob for the file name of request path
+    #[serde(default)]
+    pub files: Option<String>,
+}
+
+#[test]
+fn header_rule_test() {
+    let rules = json5::from_str::<Vec<HeaderRule>>(
+        r#"[
+            { "name": "X-Robots-Tag", "value": "noindex" },
+            { "name": "Cache-Control", "value": "immutable", "files": "*.js", "status": [200, 304] },
+        ]"#,
+    )
+    .unwrap();
+
+    assert_eq!(rules[0].name, "X-Robots-Tag");
+    assert_eq!(rules[0].value, "noindex");
+    assert!(rules[0].status.is_empty() && rules[0].files.is_none());
+    assert_eq!(rules[1].status, vec![200, 304]);
+    assert_eq!(rules[1].files.as_ref().unwrap(), "*.js");
+    assert!(json5::from_str::<Vec<HeaderRule>>(r#"[{ "value": "noindex" }]"#).is_err());
+}
+
+/// Rewrite or redirect the request path matched the regex, `target` can use the captures: `$1`, `${name}`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Rewrite {
+    #[serde(rename = "match")]
+    pub regex: String,
+    pub target: String,
+    #[serde(rename = "type", default)]
+    pub kind: RewriteKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "RewriteKindRaw", into = "RewriteKindRaw")]
+pub enum RewriteKind {
+    #[default]
+    Internal,
+    Redirect(u16),
+}
+
+// "internal", 301, 302, 307, 308 or "301"..
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RewriteKindRaw {
+    Code(u16),
+    Name(String),
+}
+
+impl TryFrom<RewriteKindRaw> for RewriteKind {
+    type Error = String;
+
+    fn try_from(raw: RewriteKindRaw) -> std::result::Result<Self, Self::Error> {
+        let code = match raw {
+            RewriteKindRaw::Name(name) if name.eq_ignore_ascii_case("internal") => return Ok(RewriteKind::Internal),
+            RewriteKindRaw::Name(name) => name.parse::<u16>().map_err(|_| format!("invalid rewrite type: {:?}", name))?,
+            RewriteKindRaw::Code(code) => code,
+        };
+
+        match code {
+            301 | 302 | 307 | 308 => Ok(RewriteKind::Redirect(code)),
+            _ => Err(format!(
+                "invalid rewrite type: {}, expect internal, 301, 302, 307 or 308",
+                code
+            )),
+        }
+    }
+}
+
+impl From<RewriteKind> for RewriteKindRaw {
+    fn from(kind: RewriteKind) -> Self {
+        match kind {
+            RewriteKind::Internal => RewriteKindRaw::Name("internal".to_owned()),
+            RewriteKind::Redirect(code) => RewriteKindRaw::Code(code),
+        }
+    }
+}
+
+#[test]
+fn rewrite_test() {
+    let rewrite = |s: &str| json5::from_str::<Rewrite>(s);
+
+    let r = rewrite(r#"{"match": "^/old/(.*)$", "target": "/new/$1"}"#).unwrap();
+    assert_eq!(r.kind, RewriteKind::Internal);
+    assert_eq!(r.target, "/new/$1");
+    assert_eq!(
+        rewrite(r#"{"match": "", "target": "", "type": 301}"#).unwrap().kind,
+        RewriteKind::Redirect(301)
+    );
+    assert_eq!(
+        rewrite(r#"{"match": "", "target": "", "type": "308"}"#).unwrap().kind,
+        RewriteKind::Redirect(308)
+    );
+    assert_eq!(
+        rewrite(r#"{"match": "", "target": "", "type": "Internal"}"#).unwrap().kind,
+        RewriteKind::Internal
+    );
+    assert!(rewrite(r#"{"match": "", "target": "", "type": 303}"#).is_err());
+    assert!(rewrite(r#"{"match": "", "target": "", "type": "moved"}"#).is_err());
+}
+
 pub fn load_certs(path: &str) -> Result<Vec<rustls::Certificate>> {
     let certfile = fs::File::open(path).map_err(|e| format_err!("open certificate file({}) failed: {:?}", path, e))?;
     let mut reader = io::BufReader::new(certfile);
@@ -156,6 +386,8 @@ pub fn load_private_key(path: &str) -> Result<rustls::PrivateKey> {
 #[derive(Debug, Default, Deserialize, PartialEq, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Route {
+    // not used by the upstream route
+    #[serde(default)]
     pub path: String,
     #[serde(default)]
     #[serde(skip)]
@@ -167,6 +399,27 @@ pub struct Route {
     pub follow_links: bool,
     #[serde(default)]
     pub redirect_html: bool,
+    // serve the index file at the directory url with 200, instead of redirecting to it
+    #[serde(default)]
+    pub serve_index: bool,
+    // the candidates of index file, in order
+    #[serde(default)]
+    pub index_files: Option<Vec<String>>,
+    // serve `app.js.br` or `app.js.gz` for `app.js` if the client accepts
+    #[serde(default)]
+    pub precompressed: bool,
+    // the strong ETag from the hash of content, instead of the weak one from the size and mtime
+    #[serde(default)]
+    pub strong_etag: bool,
+    // keep the small files in memory, with their content type and ETag
+    #[serde(default)]
+    pub file_cache: Option<FileCacheConfig>,
+    // the extensions(`.html`) tried in order for the missing paths, `/about` -> `about.html`
+    #[serde(default)]
+    pub try_extensions: Vec<String>,
+    // 301 from `/about.html` to `/about`
+    #[serde(default)]
+    pub trim_extensions: bool,
     #[serde(default)]
     pub show_hider: bool,
     #[serde(default)]
@@ -177,6 +430,32 @@ pub struct Route {
     pub upload: bool,
     #[serde(default)]
     pub mkdir: bool,
+    // the allowed methods, like `["GET", "HEAD"]` for read-only, the methods handler supports by default
+    #[serde(default)]
+    pub methods: Option<Vec<String>>,
+    // gitignore-style patterns hide the entries from index and direct access, with the `.fht2pignore`s
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // the file(relative to path) served for the missing paths without extension
+    #[serde(default)]
+    pub spa_fallback: Option<String>,
+    // after the global headers
+    #[serde(default)]
+    pub headers: Vec<HeaderRule>,
+    // status(`404`) or class(`5xx`) -> file(relative to path), before the global ones
+    #[serde(default)]
+    pub error_pages: Map<String, String>,
+    // override the global ones
+    #[serde(default)]
+    pub cache_secs: Option<u32>,
+    #[serde(default)]
+    pub compress_level: Option<u32>,
+    #[serde(default)]
+    pub magic_limit: Option<u64>,
+    // forward the requests to `http(s)://host:port/prefix/`, the url of route is replaced by the prefix,
+    // it's kept if the upstream has no path
+    #[serde(default)]
+    pub upstream: Option<String>,
 }
 
 macro_rules! route_builder {
@@ -206,9 +485,50 @@ impl Route {
         }
     }
 
+    pub fn new_upstream<U, P>(url: U, upstream: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        Self {
+            url: url.into(),
+            upstream: Some(upstream.into()),
+            ..Default::default()
+        }
+    }
+
+    /// The upstream or the path
+    pub fn target(&self) -> &str {
+        self.upstream.as_deref().unwrap_or(&self.path)
+    }
+
+    /// The `indexFiles` or `["index.html", "index.htm"]`
+    pub fn index_files(&self) -> Vec<&str> {
+        match &self.index_files {
+            Some(files) => files.iter().map(|f| f.as_str()).collect(),
+            None => INDEX_FILES.to_vec(),
+        }
+    }
+
+    pub fn cache_secs(&self, config: &Config) -> u32 {
+        self.cache_secs.unwrap_or(config.cache_secs)
+    }
+
+    pub fn compress_level(&self, config: &Config) -> u32 {
+        self.compress_level.unwrap_or(config.compress_level)
+    }
+
+    pub fn magic_limit(&self, config: &Config) -> u64 {
+        self.magic_limit.unwrap_or(config.magic_limit)
+    }
+
     route_builder! {
         disable_index,
         redirect_html,
+        serve_index,
+        trim_extensions,
+        precompressed,
+        strong_etag,
         follow_links,
         show_hider,
         authorized,
@@ -223,17 +543,30 @@ impl Default for Config {
         map.insert("/".to_owned(), Route::new("/", "."));
         Config {
             addr: Server::default().into(),
-            magic_limit: *MAGIC_LIMIT.get(),
+            magic_limit: MAGIC_LIMIT,
             compress_level: COMPRESS_LEVEL,
+            brotli_level: BROTLI_LEVEL,
+            zstd_level: ZSTD_LEVEL,
             cors: Default::default(),
             show_qrcode: false,
             keep_alive: true,
+            sendfile: true,
             cache_secs: 60,
             proxy: None,
             routes: map,
+            vhosts: Map::new(),
+            rewrites: vec![],
+            headers: vec![],
+            error_pages: Map::new(),
             auth: None,
             cert: None,
             hsts_header: None,
+            trusted_proxies: vec![],
+            config_path: None,
+            pid_file: None,
+            worker_threads: worker_threads(),
+            blocking_threads: BLOCKING_THREADS,
+            thread_stack_size: THREAD_STACK_SIZE,
         }
     }
 }
@@ -243,16 +576,37 @@ impl Default for Config {
 pub struct Config {
     pub show_qrcode: bool,
     pub keep_alive: bool,
+    // send the files by `sendfile` on the plain TCP connections of Linux
+    pub sendfile: bool,
     pub cache_secs: u32,
     pub magic_limit: u64,
     pub addr: SocketAddr,
     pub routes: Map<String, Route>,
+    // host(`example.com` or `*.example.com`) -> routes, the `routes` is the default
+    pub vhosts: Map<String, Map<String, Route>>,
     pub auth: Option<Auth>,
     pub cert: Option<Cert>,
     pub proxy: Option<Route>,
     pub compress_level: u32,
+    // the compressLevel 0 disables them too
+    pub brotli_level: u32,
+    pub zstd_level: i32,
     pub cors: CorsConfig,
+    // for all responses, the headers of route after them
+    pub headers: Vec<HeaderRule>,
+    // status(`404`) or class(`5xx`) -> file, replace the built-in error pages
+    pub error_pages: Map<String, String>,
+    // in order, before the matching of routes
+    pub rewrites: Vec<Rewrite>,
     pub hsts_header: Option<String>,
+    pub trusted_proxies: Vec<Cidr>,
+    // reload it on SIGHUP
+    pub config_path: Option<String>,
+    // remove it on exit
+    pub pid_file: Option<String>,
+    pub worker_threads: usize,
+    pub blocking_threads: usize,
+    pub thread_stack_size: usize,
 }
 
 impl Config {
@@ -270,8 +624,6 @@ impl Config {
             // values: h2, http/1.1
             cfg.set_protocols(&[b"http/1.1".to_vec()]);
 
-            HSTS_HEADER.set(cert.hsts.as_ref().and_then(|c| c.to_header()));
-
             return Ok(Some(TlsAcceptor::from(Arc::new(cfg))));
         }
 
@@ -11,16 +11,33 @@ pub const CONFIG_STRING: &str = include_str!("../config/fht2p.json");
 
 pub const CHARSET: &str = "charset=utf-8";
 pub const CONTENT_TYPE_HTML: &str = "text/html; charset=utf-8";
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+pub const INDEX_FILES: &[&str] = &["index.html", "index.htm"];
 pub const COMPRESS_LEVEL: u32 = 5;
+pub const BROTLI_LEVEL: u32 = 5;
+pub const ZSTD_LEVEL: i32 = 3;
+// the smaller files are not compressed on the fly
+pub const COMPRESS_FILE_MIN: u64 = 1024;
+// the memory cache of files of route
+pub const FILE_CACHE_SIZE: u64 = 1024 * 1024 * 8;
+pub const FILE_CACHE_FILE_SIZE: u64 = 1024 * 64;
+// the strong ETags of route
+pub const ETAG_CACHE_SIZE: usize = 1024;
+//  10485760 = 10M
+pub const MAGIC_LIMIT: u64 = 1024 * 1024 * 10;
+// tokio's default max_threads is 512
+pub const BLOCKING_THREADS: usize = 512;
+pub const THREAD_STACK_SIZE: usize = 1024 * 1024 * 2;
+
+pub fn worker_threads() -> usize {
+    num_cpus::get() * 2 + 1
+}
 
 use std::cell::UnsafeCell;
 use std::net::{Ipv4Addr, SocketAddr};
 
 lazy_static! {
-    //  10485760 = 10M
-    pub static ref MAGIC_LIMIT: MutStatic<u64> = (1024*1024*10).into();
-    pub static ref HSTS_HEADER: MutStatic<Option<String>> = Default::default();
-    pub static ref SERVER_ADDR: MutStatic<SocketAddr> = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8000).into();
+    pub static ref SERVER_ADDR: SocketAddr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8000);
 }
 
 /// it's unsync, but only modify it before read it concurrent, lazy to use `RwLock`..
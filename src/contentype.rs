@@ -4,7 +4,7 @@ use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 use std::{mem, str};
 
-use crate::consts::{MutStatic, CHARSET, MAGIC_LIMIT};
+use crate::consts::{MutStatic, CHARSET};
 
 /**
 `Content-Type`
@@ -15,14 +15,14 @@ use crate::consts::{MutStatic, CHARSET, MAGIC_LIMIT};
 
 [`mime_guess`](https://github.com/abonander/mime_guess/blob/master/src/mime_types.rs)
 */
-pub fn guess_contentype(file: &mut File, metadata: &Metadata, path: &Path) -> io::Result<String> {
+pub fn guess_contentype(file: &mut File, metadata: &Metadata, path: &Path, magic_limit: u64) -> io::Result<String> {
     let str = if let Some(mime) = mime_guess::from_path(path).first() {
         if mime.type_() == "text" {
             format!("{}/{};{}", mime.type_(), mime.subtype(), CHARSET)
         } else {
             format!("{}/{}", mime.type_(), mime.subtype())
         }
-    } else if *MAGIC_LIMIT.get() > metadata.len() {
+    } else if magic_limit > metadata.len() {
         let (is_text, _offset) = is_text(file)?;
         if is_text {
             format!("text/plain; {}", CHARSET)
@@ -0,0 +1,193 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::how::Result;
+
+pub const PID_FILE_NAME: &str = "fht2p.pid";
+pub const LOG_FILE_NAME: &str = "fht2p.log";
+
+// the daemon writes it to the pipe after the server bound
+const STARTUP_OK: &str = "ok";
+
+lazy_static! {
+    // the write end of the pipe, the parent waits on it for the startup result
+    static ref STARTUP_PIPE: Mutex<Option<fs::File>> = Mutex::new(None);
+}
+
+/// Report the startup result to the parent of the daemon, only the first one is sent
+pub fn notify_startup<E: Display>(result: std::result::Result<(), E>) {
+    if let Some(mut pipe) = STARTUP_PIPE.lock().unwrap().take() {
+        let msg = result.map(|()| STARTUP_OK.to_owned()).unwrap_or_else(|e| e.to_string());
+        pipe.write_all(msg.as_bytes()).ok();
+    }
+}
+
+pub fn default_pid_file() -> String {
+    std::env::temp_dir().join(PID_FILE_NAME).to_string_lossy().into_owned()
+}
+
+pub fn default_log_file() -> String {
+    std::env::temp_dir().join(LOG_FILE_NAME).to_string_lossy().into_owned()
+}
+
+/// Read the pid from pid file, `None` if the file not exists
+pub fn read_pid(pid_file: &str) -> Result<Option<i32>> {
+    match fs::read_to_string(pid_file) {
+        Ok(s) => i32::from_str(s.trim())
+            .map(Some)
+            .map_err(|e| format_err!("pid file('{}') is invalid: {}", pid_file, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format_err!("pid file('{}') read failed: {}", pid_file, e)),
+    }
+}
+
+pub fn remove_pid_file(pid_file: &str) {
+    if Path::new(pid_file).exists() {
+        fs::remove_file(pid_file)
+            .map_err(|e| error!("remove pid file('{}') failed: {}", pid_file, e))
+            .ok();
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: i32) -> bool {
+    // signal 0 only checks the existence
+    unsafe { libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+/// Fork and detach from the terminal, the stdout and stderr of the daemon are redirected to `log_file`,
+/// it must be called before any thread(such as the logger's) started.
+///
+/// The parent exits after the daemon calls `notify_startup` or exits, with its result.
+#[cfg(unix)]
+pub fn daemonize(pid_file: &str, log_file: &str) -> Result<()> {
+    use std::{
+        fs::{File, OpenOptions},
+        io::Read,
+        os::unix::io::{AsRawFd, FromRawFd},
+    };
+
+    if let Some(pid) = read_pid(pid_file)? {
+        if is_alive(pid) {
+            return Err(format_err!("fht2p is running already, pid: {}({})", pid, pid_file));
+        }
+        eprintln!("remove the stale pid file('{}'): {}", pid_file, pid);
+        fs::remove_file(pid_file)?;
+    }
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .map_err(|e| format_err!("log file('{}') open failed: {}", log_file, e))?;
+    let null = OpenOptions::new().read(true).open("/dev/null")?;
+
+    // no pipe2 on the macOS
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(format_err!("pipe failed: {}", io::Error::last_os_error()));
+    }
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for fd in &fds {
+        if unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(format_err!("pipe's fcntl failed: {}", io::Error::last_os_error()));
+        }
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(format_err!("fork failed: {}", io::Error::last_os_error())),
+        0 => {
+            drop(reader);
+            *STARTUP_PIPE.lock().unwrap() = Some(writer);
+        }
+        pid => {
+            drop(writer);
+            let mut msg = String::new();
+            reader.read_to_string(&mut msg).ok();
+
+            if msg == STARTUP_OK {
+                println!("fht2p daemon started, pid: {}, log: {}", pid, log_file);
+                crate::process_exit(0);
+            }
+            if msg.is_empty() {
+                msg = "exited".to_owned();
+            }
+            eprintln!("fht2p daemon({}) startup failed: {}, log: {}", pid, msg, log_file);
+            crate::process_exit(1);
+        }
+    }
+
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(format_err!("setsid failed: {}", io::Error::last_os_error()));
+        }
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    fs::write(pid_file, format!("{}\n", std::process::id()))
+        .map_err(|e| format_err!("pid file('{}') write failed: {}", pid_file, e))
+}
+
+/// Send SIGTERM to the pid recorded in `pid_file` and wait it to exit
+#[cfg(unix)]
+pub fn stop(pid_file: &str) -> Result<()> {
+    use std::{thread, time::Duration};
+
+    let pid = read_pid(pid_file)?.ok_or_else(|| format_err!("pid file('{}') not exists", pid_file))?;
+
+    if !is_alive(pid) {
+        remove_pid_file(pid_file);
+        return Err(format_err!("fht2p({}) is not running, stale pid file removed", pid));
+    }
+
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(format_err!("stop fht2p({}) failed: {}", pid, io::Error::last_os_error()));
+    }
+
+    for _ in 0..50 {
+        if !is_alive(pid) {
+            remove_pid_file(pid_file);
+            println!("fht2p({}) stopped", pid);
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Err(format_err!("fht2p({}) is still running after 5s", pid))
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_pid_file: &str, _log_file: &str) -> Result<()> {
+    Err(format_err!("daemon mode is only supported on unix"))
+}
+
+#[cfg(not(unix))]
+pub fn stop(_pid_file: &str) -> Result<()> {
+    Err(format_err!("daemon mode is only supported on unix"))
+}
+
+#[test]
+fn read_pid_test() {
+    let path = std::env::temp_dir().join("fht2p-read-pid-test.pid");
+    let path = path.to_str().unwrap();
+
+    fs::remove_file(path).ok();
+    assert_eq!(read_pid(path).unwrap(), None);
+
+    fs::write(path, "123\n").unwrap();
+    assert_eq!(read_pid(path).unwrap(), Some(123));
+
+    fs::write(path, "abc").unwrap();
+    assert!(read_pid(path).is_err());
+
+    remove_pid_file(path);
+    assert!(!Path::new(path).exists());
+}
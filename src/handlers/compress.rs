@@ -1,69 +1,69 @@
+use brotli::CompressorWriter;
 use flate2::{
     write::{DeflateEncoder, GzEncoder},
     Compression,
 };
 use hyper::header;
 use tokio::task::spawn_blocking;
+use zstd::stream::write::Encoder as ZstdEncoder;
 
 use std::io::{self, Write};
 use std::net::SocketAddr;
+use std::path::Path;
 
 use super::exception::exception_handler_sync;
 use crate::base::{http, Body, HeaderGetStr, Request, Response, ResponseBuilder};
+use crate::config::{Config, Route};
+use crate::tools::QValues;
 
-// bitflags! {
-//     pub struct Algorithms: u8 {
-//         const NONE =  0b00000000;
-//         const DEFLATE = 0b10000000;
-//         const GZIP =  0b01000000;
-//         const SDCN =0b00100000;
-//         const BR = 0b00010000;
-//         const ZSTD = 0b00001000;
-//     }
-// }
+/// The levels of algorithms, gzip and deflate 0~9(0 disables all), brotli 0~11, zstd 1~21
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressLevels {
+    pub flate: u32,
+    pub brotli: u32,
+    pub zstd: i32,
+}
+
+impl CompressLevels {
+    pub fn new(route: &Route, config: &Config) -> Self {
+        Self {
+            flate: route.compress_level(config),
+            brotli: config.brotli_level,
+            zstd: config.zstd_level,
+        }
+    }
+    pub fn is_disabled(&self) -> bool {
+        self.flate == 0
+    }
+}
 
 pub struct Compressor {
     algorithm: &'static str,
-    compress_level: Compression,
+    levels: CompressLevels,
 }
 
 const GZIP: &str = "gzip";
 const DEFLATE: &str = "deflate";
+const BR: &str = "br";
+const ZSTD: &str = "zstd";
+// the preference of server among the equal q-values
+const ALGORITHMS: &[&str] = &[BR, ZSTD, GZIP, DEFLATE];
+
+// the window of brotli, the default of its cli
+const BROTLI_LGWIN: u32 = 22;
+const BROTLI_BUFFER: usize = 4096;
 
 impl Compressor {
     pub fn algorithm(&self) -> &'static str {
         self.algorithm
     }
-    pub fn new<S>(accept_encodings_str: S, compress_level: u32) -> Result<Self, S>
+    pub fn new<S>(accept_encodings_str: S, levels: CompressLevels) -> Result<Self, S>
     where
         S: AsRef<str>,
     {
-        let compress_level = Compression::new(compress_level);
-
-        // Algorithm priority is selected by the browser
-        let mut algorithm = "";
-        for value in accept_encodings_str.as_ref().split(',') {
-            let algs = value.trim();
-            match algs {
-                GZIP => {
-                    algorithm = GZIP;
-                    break;
-                }
-                DEFLATE => {
-                    algorithm = DEFLATE;
-                    break;
-                }
-                _ => {}
-            }
-        }
-
-        if algorithm.is_empty() {
-            Err(accept_encodings_str)
-        } else {
-            Ok(Self {
-                algorithm,
-                compress_level,
-            })
+        match AcceptEncoding::parse(accept_encodings_str.as_ref()).negotiate(ALGORITHMS) {
+            Some(algorithm) => Ok(Self { algorithm, levels }),
+            None => Err(accept_encodings_str),
         }
     }
 
@@ -74,18 +74,240 @@ impl Compressor {
     {
         match self.algorithm {
             GZIP => {
-                let mut encoder = GzEncoder::new(writer, self.compress_level);
+                let mut encoder = GzEncoder::new(writer, Compression::new(self.levels.flate));
                 encoder.write_all(input.as_ref())?;
                 encoder.finish()
             }
             DEFLATE => {
-                let mut encoder = DeflateEncoder::new(writer, self.compress_level);
+                let mut encoder = DeflateEncoder::new(writer, Compression::new(self.levels.flate));
+                encoder.write_all(input.as_ref())?;
+                encoder.finish()
+            }
+            BR => {
+                let mut encoder = CompressorWriter::new(writer, BROTLI_BUFFER, self.levels.brotli, BROTLI_LGWIN);
+                encoder.write_all(input.as_ref())?;
+                Ok(encoder.into_inner())
+            }
+            ZSTD => {
+                let mut encoder = ZstdEncoder::new(writer, self.levels.zstd)?;
                 encoder.write_all(input.as_ref())?;
                 encoder.finish()
             }
             _ => unreachable!(),
         }
     }
+
+    /// The streaming one, the compressed is taken from the inner `Vec`
+    pub fn encoder(&self) -> io::Result<Encoder> {
+        let encoder = match self.algorithm {
+            GZIP => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(self.levels.flate))),
+            DEFLATE => Encoder::Deflate(DeflateEncoder::new(Vec::new(), Compression::new(self.levels.flate))),
+            BR => Encoder::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                self.levels.brotli,
+                BROTLI_LGWIN,
+            ))),
+            ZSTD => Encoder::Zstd(ZstdEncoder::new(Vec::new(), self.levels.zstd)?),
+            _ => unreachable!(),
+        };
+        Ok(encoder)
+    }
+}
+
+pub enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Zstd(ZstdEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Compress the input and take the output so far
+    pub fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match self {
+            Encoder::Gzip(e) => {
+                e.write_all(input)?;
+                e.get_mut()
+            }
+            Encoder::Deflate(e) => {
+                e.write_all(input)?;
+                e.get_mut()
+            }
+            Encoder::Brotli(e) => {
+                e.write_all(input)?;
+                e.get_mut()
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(input)?;
+                e.get_mut()
+            }
+        };
+        Ok(std::mem::take(output))
+    }
+
+    /// The rest of output
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Deflate(e) => e.finish(),
+            Encoder::Brotli(e) => Ok(e.into_inner()),
+            Encoder::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+/// The text like types, the images, videos and archives are compressed already
+pub fn is_compressible(path: &Path) -> bool {
+    mime_guess::from_path(path)
+        .first()
+        .map(|mime| {
+            let (typo, subtype) = (mime.type_().as_str(), mime.subtype().as_str());
+            let suffix = mime.suffix().map(|s| s.as_str());
+            match (typo, subtype) {
+                ("text", _) => true,
+                ("application", "json") | ("application", "javascript") | ("application", "xml") => true,
+                ("application", "wasm") | ("application", "x-sh") | ("application", "toml") => true,
+                _ => suffix == Some("json") || suffix == Some("xml"),
+            }
+        })
+        .unwrap_or_default()
+}
+
+#[test]
+fn is_compressible_test() {
+    for file in &[
+        "a.txt", "a.log", "a.json", "a.js", "a.css", "a.html", "a.svg", "a.xml", "a.csv",
+    ] {
+        assert!(is_compressible(Path::new(file)), "{}", file);
+    }
+    for file in &["a.png", "a.jpg", "a.mp4", "a.gz", "a.zip", "a.woff2", "a", "a.unknown"] {
+        assert!(!is_compressible(Path::new(file)), "{}", file);
+    }
+}
+
+#[test]
+fn encoder_test() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let levels = CompressLevels {
+        flate: 5,
+        brotli: 5,
+        zstd: 3,
+    };
+    let text = "fht2p is a simple http server\n".repeat(100);
+
+    for algorithm in &[GZIP, BR, ZSTD] {
+        let compressor = Compressor::new(*algorithm, levels).unwrap();
+        assert_eq!(compressor.algorithm(), *algorithm);
+
+        let mut encoder = compressor.encoder().unwrap();
+        let mut compressed = vec![];
+        for line in text.lines() {
+            compressed.extend(encoder.write(line.as_bytes()).unwrap());
+            compressed.extend(encoder.write(b"\n").unwrap());
+        }
+        compressed.extend(encoder.finish().unwrap());
+
+        let decompress = |compressed: &[u8]| {
+            let mut decompressed = String::new();
+            match *algorithm {
+                GZIP => GzDecoder::new(compressed).read_to_string(&mut decompressed),
+                BR => brotli::Decompressor::new(compressed, 4096).read_to_string(&mut decompressed),
+                _ => zstd::stream::read::Decoder::new(compressed)
+                    .unwrap()
+                    .read_to_string(&mut decompressed),
+            }
+            .unwrap();
+            decompressed
+        };
+        assert_eq!(decompress(&compressed), text, "{}", algorithm);
+        let compressed = compressor.compress(&text, Vec::new()).unwrap();
+        assert_eq!(decompress(&compressed), text, "{}", algorithm);
+    }
+
+    assert!(Compressor::new("identity, x-gzip", levels).is_err());
+    assert_eq!(Compressor::new("zstd, br, gzip", levels).unwrap().algorithm(), BR);
+    assert_eq!(Compressor::new("br;q=0.5, zstd", levels).unwrap().algorithm(), ZSTD);
+}
+
+/// The parsed `Accept-Encoding`, RFC 9110 12.5.3
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AcceptEncoding {
+    codings: QValues,
+}
+
+impl AcceptEncoding {
+    pub fn parse(accept_encodings_str: &str) -> Self {
+        Self {
+            codings: QValues::parse(accept_encodings_str),
+        }
+    }
+
+    /// The q-value of `coding`, or `*`'s, the `identity` is acceptable unless excluded
+    pub fn qvalue(&self, coding: &str) -> u16 {
+        self.explicit(coding)
+            .unwrap_or(if coding.eq_ignore_ascii_case("identity") { 1000 } else { 0 })
+    }
+
+    /// The q-value of `identity` to beat, the codings win the implicit one
+    pub fn identity(&self) -> u16 {
+        self.explicit("identity").unwrap_or(0)
+    }
+
+    fn explicit(&self, coding: &str) -> Option<u16> {
+        self.codings.get(coding).or_else(|| self.codings.get("*"))
+    }
+
+    /// The acceptable one with the highest q-value, the former of `supported` first among the equals,
+    /// None if the `identity` is preferred
+    pub fn negotiate<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        let (coding, q) = supported.iter().fold(None, |best: Option<(&'a str, u16)>, coding| {
+            let q = self.qvalue(coding);
+            match best {
+                Some((_, bq)) if bq >= q => best,
+                _ if q > 0 => Some((coding, q)),
+                _ => best,
+            }
+        })?;
+
+        if q >= self.identity() {
+            Some(coding)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn accept_encoding_test() {
+    let accept_encoding = |s: &str, encoding: &str| AcceptEncoding::parse(s).qvalue(encoding) > 0;
+    assert!(accept_encoding("gzip, deflate, br", "br"));
+    assert!(accept_encoding("GZIP;q=0.5", "gzip"));
+    assert!(!accept_encoding("gzip;q=0, deflate", "gzip"));
+    assert!(!accept_encoding("gzip;q=0.000", "gzip"));
+    assert!(!accept_encoding("x-gzip", "gzip"));
+    assert!(!accept_encoding("", "gzip"));
+    assert!(accept_encoding("*", "br"));
+    assert!(!accept_encoding("*;q=0, gzip", "br"));
+    assert!(!accept_encoding("gzip;q=1.5", "gzip"));
+    assert!(!accept_encoding("gzip;q=abc", "gzip"));
+
+    let negotiate = |s: &str| AcceptEncoding::parse(s).negotiate(ALGORITHMS);
+    assert_eq!(negotiate("gzip;q=1.0"), Some(GZIP));
+    assert_eq!(negotiate("gzip, deflate, br"), Some(BR));
+    assert_eq!(negotiate("gzip;q=0.8, deflate;q=0.9"), Some(DEFLATE));
+    assert_eq!(negotiate("gzip;q=0.5, *;q=0.6"), Some(BR));
+    assert_eq!(negotiate("*;q=0.5, br;q=0, zstd;q=0"), Some(GZIP));
+    assert_eq!(negotiate("identity, gzip;q=0.5"), None);
+    assert_eq!(negotiate("identity;q=0.5, gzip;q=0.5"), Some(GZIP));
+    assert_eq!(negotiate("*;q=0"), None);
+    assert_eq!(negotiate("identity"), None);
+    assert_eq!(negotiate(""), None);
+    assert_eq!(AcceptEncoding::parse("gzip").identity(), 0);
+    assert_eq!(AcceptEncoding::parse("gzip").qvalue("identity"), 1000);
+    assert_eq!(AcceptEncoding::parse("gzip, *;q=0.3").identity(), 300);
 }
 
 pub async fn compress_handler<B>(
@@ -93,20 +315,21 @@ pub async fn compress_handler<B>(
     addr: &SocketAddr,
     mut resp: ResponseBuilder,
     body: B,
-    compress_level: u32,
+    levels: CompressLevels,
 ) -> Result<Response, http::Error>
 where
     B: Into<Body> + AsRef<[u8]> + Send + 'static,
 {
     let bodysize = body.as_ref().len();
 
-    if compress_level == 0 || bodysize <= 32 {
+    if levels.is_disabled() || bodysize <= 32 {
         return resp.body(body.into());
     }
 
     let accept_encodings_str = req.headers().get_str(header::ACCEPT_ENCODING);
+    resp = resp.header(header::VARY, "Accept-Encoding");
 
-    match Compressor::new(accept_encodings_str, compress_level) {
+    match Compressor::new(accept_encodings_str, levels) {
         Ok(compressor) => {
             let algs = compressor.algorithm();
             let res = spawn_blocking(move || compressor.compress(&body, Vec::new()).map_err(move |e| (e, body))).await;
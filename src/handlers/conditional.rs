@@ -0,0 +1,273 @@
+use chrono::{offset::Local, DateTime, NaiveDateTime, TimeZone, Utc};
+use hyper::{header, Method};
+use sha2::{Digest, Sha256};
+
+use std::{
+    fs::Metadata,
+    io::{self, Read},
+    time::SystemTime,
+};
+
+use crate::base::{HeaderGetStr, Request};
+
+/// The validators of the selected representation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validators {
+    // quoted, with `W/` for the weak one
+    pub etag: String,
+    // the precision of HTTP-date is second
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    pub fn new(etag: String, modified: SystemTime) -> Self {
+        let modified: DateTime<Utc> = modified.into();
+        Self {
+            etag,
+            last_modified: Utc.timestamp_opt(modified.timestamp(), 0).unwrap(),
+        }
+    }
+
+    pub fn http_last_modified(&self) -> String {
+        httpdate(&self.last_modified)
+    }
+}
+
+/// The result of preconditions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precondition {
+    /// Go on, serve the `Range` or not(absent or `If-Range` failed)
+    Proceed(bool),
+    // 304
+    NotModified,
+    // 412
+    Failed,
+}
+
+/// Evaluate the preconditions in the order of RFC 9110 13.2.2, the invalid dates are ignored
+pub fn evaluate(req: &Request, validators: &Validators) -> Precondition {
+    let headers = req.headers();
+    let get_or_head = req.method() == Method::GET || req.method() == Method::HEAD;
+    let date = |name| headers.get_str_option(name).and_then(parse_httpdate);
+
+    if let Some(if_match) = headers.get_str_option(header::IF_MATCH) {
+        if !etag_matches(if_match, &validators.etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = date(header::IF_UNMODIFIED_SINCE) {
+        if validators.last_modified > since {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(if_none_match) = headers.get_str_option(header::IF_NONE_MATCH) {
+        if etag_matches(if_none_match, &validators.etag, false) {
+            return if get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let Some(since) = date(header::IF_MODIFIED_SINCE).filter(|_| get_or_head) {
+        if validators.last_modified <= since {
+            return Precondition::NotModified;
+        }
+    }
+
+    let range = headers.contains_key(header::RANGE)
+        && headers
+            .get_str_option(header::IF_RANGE)
+            .map(|if_range| match if_range.trim() {
+                v if v.starts_with('"') || v.starts_with("W/") => etag_matches(v, &validators.etag, true),
+                v => parse_httpdate(v).map(|d| d == validators.last_modified).unwrap_or_default(),
+            })
+            .unwrap_or(true);
+    Precondition::Proceed(range)
+}
+
+/// Whether the `*` or the list of entity-tags contains the `etag`, the weak ones never match strongly
+pub fn etag_matches(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_owned();
+    let weak = etag.starts_with("W/");
+    parse_etags(list)
+        .into_iter()
+        .any(|tag| (!strong || !(weak || tag.starts_with("W/"))) && opaque(tag) == opaque(etag))
+}
+
+// `W/"a", "b,c"` -> [`W/"a"`, `"b,c"`], the comma is valid in the quotes
+fn parse_etags(list: &str) -> Vec<&str> {
+    let mut etags = vec![];
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        let start = if rest.starts_with("W/\"") { 2 } else { 0 };
+        if !rest[start..].starts_with('"') {
+            break;
+        }
+        match rest[start + 1..].find('"') {
+            Some(idx) => {
+                let end = start + idx + 2;
+                etags.push(&rest[..end]);
+                rest = &rest[end..];
+            }
+            None => break,
+        }
+    }
+    etags
+}
+
+/// IMF-fixdate, `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn httpdate(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// IMF-fixdate and the obsolete RFC 850 and asctime formats
+pub fn parse_httpdate(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+    .map(|date| Utc.from_utc_datetime(&date))
+}
+
+/// The weak ETag from the size and mtime, `W/"80-5d564a70.3797f8b1"`
+pub fn weak_etag(meta: &Metadata) -> io::Result<String> {
+    let last_modified: DateTime<Local> = meta.modified()?.into();
+    Ok(format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        meta.len(),
+        last_modified.timestamp_millis(),
+        last_modified.timestamp_subsec_nanos(),
+    ))
+}
+
+/// The strong ETag from the SHA-256 of content, `"9f86d081884c7d65"`
+pub fn content_etag<R: Read>(mut content: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut content, &mut hasher)?;
+    let hash = hasher.finalize();
+
+    let mut etag = String::with_capacity(40);
+    etag.push('"');
+    hash[..8].iter().for_each(|b| etag.push_str(&format!("{:02x}", b)));
+    etag.push('"');
+    Ok(etag)
+}
+
+/// The ETag of the encoded, `W/"80-5d564a70.3797f8b1-gzip"` or `"9f86d081884c7d65-br"`
+pub fn with_encoding(etag: &str, encoding: Option<&str>) -> String {
+    match (encoding, etag.strip_suffix('"')) {
+        (Some(encoding), Some(opaque)) => format!("{}-{}\"", opaque, encoding),
+        _ => etag.to_owned(),
+    }
+}
+
+#[test]
+fn etag_matches_test() {
+    assert!(etag_matches("*", "W/\"a\"", true));
+    assert!(etag_matches("\"x\", \"a\"", "\"a\"", true));
+    assert!(etag_matches("W/\"a\"", "\"a\"", false));
+    assert!(!etag_matches("W/\"a\"", "\"a\"", true));
+    assert!(!etag_matches("\"a\"", "W/\"a\"", true));
+    assert!(etag_matches("\"b,c\", W/\"a\"", "W/\"a\"", false));
+    assert!(!etag_matches("\"b,c\"", "\"b\"", false));
+    assert!(!etag_matches("a", "\"a\"", false));
+    assert!(!etag_matches("", "\"a\"", false));
+}
+
+#[test]
+fn etag_test() {
+    assert_eq!(content_etag(&b"test"[..]).unwrap(), "\"9f86d081884c7d65\"");
+    assert_eq!(with_encoding("\"9f86d081884c7d65\"", Some("br")), "\"9f86d081884c7d65-br\"");
+    assert_eq!(with_encoding("W/\"80-5d564a70.0\"", Some("gzip")), "W/\"80-5d564a70.0-gzip\"");
+    assert_eq!(with_encoding("W/\"80-5d564a70.0\"", None), "W/\"80-5d564a70.0\"");
+
+    let meta = std::fs::metadata(file!()).unwrap();
+    let etag = weak_etag(&meta).unwrap();
+    assert!(etag.starts_with(&format!("W/\"{:x}-", meta.len())));
+}
+
+#[test]
+fn httpdate_test() {
+    let date = Utc.timestamp_opt(784_111_777, 0).unwrap();
+    assert_eq!(httpdate(&date), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(parse_httpdate("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+    assert_eq!(parse_httpdate("Sunday, 06-Nov-94 08:49:37 GMT"), Some(date));
+    assert_eq!(parse_httpdate("Sun Nov  6 08:49:37 1994"), Some(date));
+    assert_eq!(parse_httpdate("Sun, 06 Nov 1994 08:49:37 +0800"), None);
+}
+
+#[test]
+fn evaluate_test() {
+    use crate::base::Body;
+
+    let validators = Validators::new(
+        "\"v1\"".to_owned(),
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(100),
+    );
+    let before = httpdate(&Utc.timestamp_opt(99, 0).unwrap());
+    let after = httpdate(&Utc.timestamp_opt(100, 0).unwrap());
+    let evaluate = |method: Method, headers: &[(header::HeaderName, &str)]| {
+        let mut req = hyper::Request::builder().method(method);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        evaluate(&req.body(Body::empty()).unwrap(), &validators)
+    };
+    use Precondition::*;
+
+    assert_eq!(evaluate(Method::GET, &[]), Proceed(false));
+    assert_eq!(evaluate(Method::GET, &[(header::IF_MATCH, "\"v0\"")]), Failed);
+    assert_eq!(evaluate(Method::GET, &[(header::IF_MATCH, "\"v0\", \"v1\"")]), Proceed(false));
+    assert_eq!(evaluate(Method::GET, &[(header::IF_UNMODIFIED_SINCE, &before)]), Failed);
+    // If-Match first
+    assert_eq!(
+        evaluate(
+            Method::GET,
+            &[(header::IF_MATCH, "*"), (header::IF_UNMODIFIED_SINCE, &before)]
+        ),
+        Proceed(false)
+    );
+    assert_eq!(evaluate(Method::GET, &[(header::IF_NONE_MATCH, "W/\"v1\"")]), NotModified);
+    assert_eq!(evaluate(Method::POST, &[(header::IF_NONE_MATCH, "*")]), Failed);
+    assert_eq!(evaluate(Method::HEAD, &[(header::IF_MODIFIED_SINCE, &after)]), NotModified);
+    assert_eq!(evaluate(Method::GET, &[(header::IF_MODIFIED_SINCE, &before)]), Proceed(false));
+    // If-None-Match first
+    assert_eq!(
+        evaluate(
+            Method::GET,
+            &[(header::IF_NONE_MATCH, "\"v0\""), (header::IF_MODIFIED_SINCE, &after)]
+        ),
+        Proceed(false)
+    );
+    assert_eq!(
+        evaluate(Method::GET, &[(header::IF_MODIFIED_SINCE, "yesterday")]),
+        Proceed(false)
+    );
+
+    assert_eq!(evaluate(Method::GET, &[(header::RANGE, "bytes=0-1")]), Proceed(true));
+    assert_eq!(
+        evaluate(Method::GET, &[(header::RANGE, "bytes=0-1"), (header::IF_RANGE, "\"v1\"")]),
+        Proceed(true)
+    );
+    assert_eq!(
+        evaluate(Method::GET, &[(header::RANGE, "bytes=0-1"), (header::IF_RANGE, "W/\"v1\"")]),
+        Proceed(false)
+    );
+    assert_eq!(
+        evaluate(Method::GET, &[(header::RANGE, "bytes=0-1"), (header::IF_RANGE, &after)]),
+        Proceed(true)
+    );
+    assert_eq!(
+        evaluate(Method::GET, &[(header::RANGE, "bytes=0-1"), (header::IF_RANGE, &before)]),
+        Proceed(false)
+    );
+}
@@ -10,18 +10,23 @@ use std::convert::TryInto;
 use std::io::{self, ErrorKind};
 use std::net::SocketAddr;
 
-use crate::base::{ctx::Ctx, handler::BoxedHandler, http, response, Request, Response};
+use crate::base::{ctx::Ctx, handler::BoxedHandler, http, response, HeaderGetStr, Request, Response, ServerAddr};
 use crate::consts;
-use crate::tools::url_for_parent;
+use crate::tools::{url_for_parent, QValues};
 use crate::views::ErrorTemplate;
 
 pub fn redirect_handler_sync<S: Into<String>>(permanent: bool, dest: S) -> Result<Response, http::Error> {
+    let code = if permanent {
+        StatusCode::MOVED_PERMANENTLY
+    } else {
+        StatusCode::TEMPORARY_REDIRECT
+    };
+    redirect_status_handler_sync(code, dest)
+}
+
+pub fn redirect_status_handler_sync<S: Into<String>>(code: StatusCode, dest: S) -> Result<Response, http::Error> {
     response()
-        .status(if permanent {
-            StatusCode::MOVED_PERMANENTLY
-        } else {
-            StatusCode::TEMPORARY_REDIRECT
-        })
+        .status(code)
         .header(header::LOCATION, dest.into())
         .body(Default::default())
 }
@@ -45,17 +50,68 @@ where
 
     code.and_then(|code| {
         let title = msg.map(Cow::Borrowed).unwrap_or_else(|| Cow::Owned(code.to_string()));
+
+        if accept_json(req) {
+            let json = ErrorJson {
+                code: code.as_u16(),
+                error: code.canonical_reason().unwrap_or_default(),
+                message: &title,
+            };
+            return response()
+                .status(code)
+                .header(header::CONTENT_TYPE, consts::CONTENT_TYPE_JSON)
+                .body(serde_json::to_string(&json).expect("serde_json::to_string(ErrorJson)").into());
+        }
+
         let parent = url_for_parent(req.uri().path());
-        let template = ErrorTemplate::new(&title, &title, &parent, addr);
+        let server = server_addr(req);
+        let template = ErrorTemplate::new(&title, &title, &parent, addr, &server);
         let html = template.render().unwrap();
 
         response()
             .status(code)
             .header(header::CONTENT_TYPE, consts::CONTENT_TYPE_HTML)
+            .extension(ErrorPage)
             .body(html.into())
     })
 }
 
+/// 405 with the `Allow`, like `GET, HEAD, OPTIONS`
+pub fn method_not_allowed_handler_sync(allow: &str, req: &Request, addr: &SocketAddr) -> Result<Response, http::Error> {
+    exception_handler_sync(405, None, req, addr).map(|mut resp| {
+        if let Ok(allow) = allow.parse() {
+            resp.headers_mut().insert(header::ALLOW, allow);
+        }
+        resp
+    })
+}
+
+/// The mark of the built-in error page, it may be replaced by the `errorPages`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorPage;
+
+#[derive(Serialize)]
+struct ErrorJson<'a> {
+    code: u16,
+    error: &'a str,
+    message: &'a str,
+}
+
+// prefer the json to the html, the browsers accept html and `*/*`
+fn accept_json(req: &Request) -> bool {
+    let accept = QValues::parse(req.headers().get_str(header::ACCEPT));
+    let json = accept.media("application/json");
+    json > 0 && json > accept.media("text/html")
+}
+
+/// The address of server for views
+pub fn server_addr(req: &Request) -> SocketAddr {
+    req.extensions()
+        .get::<ServerAddr>()
+        .map(|s| s.0)
+        .unwrap_or(*consts::SERVER_ADDR)
+}
+
 pub fn io_exception_handler_sync<'a>(e: io::Error, req: &Request, addr: &'a SocketAddr) -> Result<Response, http::Error> {
     let code = match e.kind() {
         ErrorKind::NotFound => 404,
@@ -65,3 +121,33 @@ pub fn io_exception_handler_sync<'a>(e: io::Error, req: &Request, addr: &'a Sock
 
     exception_handler_sync(code, None, &req, addr)
 }
+
+#[test]
+fn exception_json_test() {
+    let req = |accept: &str| {
+        hyper::Request::get("/missing")
+            .header(header::ACCEPT, accept)
+            .body(Default::default())
+            .unwrap()
+    };
+    let addr = "127.0.0.1:8000".parse().unwrap();
+
+    let resp = exception_handler_sync(404, Some("a\u{1}\"b"), &req("application/json"), &addr).unwrap();
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], consts::CONTENT_TYPE_JSON);
+    assert!(resp.extensions().get::<ErrorPage>().is_none());
+    let body = futures::executor::block_on(crate::base::body_to_bytes(resp.into_body())).unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        serde_json::json!({ "code": 404, "error": "Not Found", "message": "a\u{1}\"b" })
+    );
+    assert!(std::str::from_utf8(&body).unwrap().contains("\\u0001"));
+
+    let is_json = |accept: &str| accept_json(&req(accept));
+    assert!(is_json("application/json, text/html;q=0"));
+    assert!(is_json("text/html;q=0.5, application/*"));
+    assert!(!is_json("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"));
+    assert!(!is_json("*/*"));
+    assert!(!is_json("application/json;q=0"));
+    assert!(!is_json("application/json;q=0.5, text/*"));
+}
@@ -0,0 +1,279 @@
+use bytes::Bytes;
+use lru::LruCache;
+use tokio::task;
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::config::{FileCacheConfig, Route};
+use crate::consts::ETAG_CACHE_SIZE;
+use crate::contentype::guess_contentype;
+use crate::handlers::conditional::{content_etag, weak_etag};
+
+/// The caches of route
+#[derive(Debug)]
+pub struct Caches {
+    pub files: Option<FileCache>,
+    pub etags: Option<EtagCache>,
+}
+
+impl Caches {
+    pub fn new(route: &Route) -> Self {
+        Self {
+            files: route.file_cache.map(FileCache::new),
+            etags: if route.strong_etag {
+                Some(EtagCache::new(ETAG_CACHE_SIZE))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// The file in memory, with its content type and the ETag of the identity
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub bytes: Bytes,
+    pub content_type: String,
+    pub etag: String,
+    pub modified: SystemTime,
+}
+
+impl CachedFile {
+    pub fn read(path: &Path, meta: &fs::Metadata, magic_limit: u64, strong_etag: bool) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let content_type = guess_contentype(&mut file, meta, path, magic_limit)?;
+
+        let mut bytes = Vec::with_capacity(meta.len() as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut bytes)?;
+
+        let etag = if strong_etag {
+            content_etag(&bytes[..])?
+        } else {
+            weak_etag(meta)?
+        };
+
+        Ok(Self {
+            bytes: bytes.into(),
+            content_type,
+            etag,
+            modified: meta.modified()?,
+        })
+    }
+
+    // the file is not modified since read
+    fn is_fresh(&self, meta: &fs::Metadata) -> bool {
+        self.bytes.len() as u64 == meta.len() && meta.modified().map(|m| m == self.modified).unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct Lru {
+    files: LruCache<PathBuf, Arc<CachedFile>>,
+    // the total of bytes
+    size: u64,
+}
+
+/// The files of route keyed by path, validated by the size and mtime, the least recently used dropped beyond the `max_size`
+#[derive(Debug)]
+pub struct FileCache {
+    config: FileCacheConfig,
+    lru: Mutex<Lru>,
+}
+
+impl FileCache {
+    pub fn new(config: FileCacheConfig) -> Self {
+        Self {
+            config,
+            lru: Mutex::new(Lru {
+                files: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    /// Whether the file is small enough to keep
+    pub fn accepts(&self, meta: &fs::Metadata) -> bool {
+        meta.len() <= self.config.max_file_size
+    }
+
+    /// The cached one, or read and keep it
+    pub fn get_or_read(
+        &self,
+        path: &Path,
+        meta: &fs::Metadata,
+        magic_limit: u64,
+        strong_etag: bool,
+    ) -> io::Result<Arc<CachedFile>> {
+        if let Some(cached) = self.get(path, meta) {
+            return Ok(cached);
+        }
+
+        let cached = Arc::new(CachedFile::read(path, meta, magic_limit, strong_etag)?);
+        self.insert(path.to_owned(), cached.clone());
+        Ok(cached)
+    }
+
+    fn get(&self, path: &Path, meta: &fs::Metadata) -> Option<Arc<CachedFile>> {
+        let mut lru = self.lru.lock().unwrap();
+        let key = path.to_owned();
+        match lru.files.get(&key) {
+            Some(cached) if cached.is_fresh(meta) => Some(cached.clone()),
+            Some(_) => {
+                if let Some(stale) = lru.files.pop(&key) {
+                    lru.size -= stale.bytes.len() as u64;
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, path: PathBuf, cached: Arc<CachedFile>) {
+        let len = cached.bytes.len() as u64;
+        if len > self.config.max_file_size {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(old) = lru.files.put(path, cached) {
+            lru.size -= old.bytes.len() as u64;
+        }
+        lru.size += len;
+
+        while lru.size > self.config.max_size {
+            match lru.files.pop_lru() {
+                Some((_, old)) => lru.size -= old.bytes.len() as u64,
+                None => break,
+            }
+        }
+    }
+}
+
+/// The strong ETags of route keyed by path, validated by the size and mtime
+#[derive(Debug)]
+pub struct EtagCache {
+    etags: Mutex<LruCache<PathBuf, (u64, SystemTime, String)>>,
+}
+
+impl EtagCache {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            etags: Mutex::new(LruCache::new(cap)),
+        }
+    }
+
+    /// The cached one, or hash the file on the blocking threads and keep it
+    pub async fn get_or_hash(&self, path: &Path, meta: &fs::Metadata) -> io::Result<String> {
+        let stamp = (meta.len(), meta.modified()?);
+        if let Some(etag) = self.get(path, stamp) {
+            return Ok(etag);
+        }
+
+        let file = path.to_owned();
+        let etag = task::spawn_blocking(move || File::open(file).and_then(content_etag))
+            .await
+            .map_err(io::Error::other)??;
+
+        // not modified while hashing
+        let meta = path.metadata()?;
+        if (meta.len(), meta.modified()?) == stamp {
+            self.etags
+                .lock()
+                .unwrap()
+                .put(path.to_owned(), (stamp.0, stamp.1, etag.clone()));
+        }
+        Ok(etag)
+    }
+
+    fn get(&self, path: &Path, stamp: (u64, SystemTime)) -> Option<String> {
+        let mut etags = self.etags.lock().unwrap();
+        match etags.get(&path.to_owned()) {
+            Some((len, modified, etag)) if (*len, *modified) == stamp => Some(etag.clone()),
+            Some(_) => {
+                etags.pop(&path.to_owned());
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[test]
+fn file_cache_test() {
+    let dir = std::env::temp_dir().join(format!("fht2p-file-cache-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, content: &str| {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        let meta = path.metadata().unwrap();
+        (path, meta)
+    };
+
+    let cache = FileCache::new(FileCacheConfig {
+        max_size: 10,
+        max_file_size: 6,
+    });
+    let (a, a_meta) = write("a.css", "aaaa");
+    let (b, b_meta) = write("b.css", "bbbb");
+    let (c, c_meta) = write("c.txt", "cccccccc");
+    assert!(cache.accepts(&a_meta));
+    assert!(!cache.accepts(&c_meta));
+
+    let cached = cache.get_or_read(&a, &a_meta, 0, true).unwrap();
+    assert_eq!(&cached.bytes[..], b"aaaa");
+    assert!(cached.content_type.starts_with("text/css"));
+    assert!(!cached.etag.starts_with("W/"));
+    assert!(cache.get(&a, &a_meta).is_some());
+    cache.get_or_read(&b, &b_meta, 0, false).unwrap();
+    assert_eq!(cache.lru.lock().unwrap().size, 8);
+
+    // the a is used recently
+    cache.get(&a, &a_meta).unwrap();
+    let (d, d_meta) = write("d.css", "dddd");
+    cache.get_or_read(&d, &d_meta, 0, false).unwrap();
+    assert!(cache.get(&b, &b_meta).is_none());
+    assert!(cache.get(&a, &a_meta).is_some());
+    assert_eq!(cache.lru.lock().unwrap().size, 8);
+
+    // modified
+    let (a, a_meta) = write("a.css", "aaaaa");
+    assert!(cache.get(&a, &a_meta).is_none());
+    assert_eq!(&cache.get_or_read(&a, &a_meta, 0, false).unwrap().bytes[..], b"aaaaa");
+    assert_eq!(cache.lru.lock().unwrap().size, 9);
+
+    cache.get_or_read(&c, &c_meta, 0, false).unwrap();
+    assert!(cache.get(&c, &c_meta).is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn etag_cache_test() {
+    let dir = std::env::temp_dir().join(format!("fht2p-etag-cache-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.bin");
+    let mut rt = tokio::runtime::Builder::new().basic_scheduler().enable_all().build().unwrap();
+
+    let cache = EtagCache::new(2);
+    fs::write(&path, "aaaa").unwrap();
+    let meta = path.metadata().unwrap();
+    let etag = rt.block_on(cache.get_or_hash(&path, &meta)).unwrap();
+    assert_eq!(etag, content_etag(&b"aaaa"[..]).unwrap());
+    assert_eq!(cache.get(&path, (meta.len(), meta.modified().unwrap())), Some(etag.clone()));
+
+    // modified
+    fs::write(&path, "aaaaa").unwrap();
+    let meta = path.metadata().unwrap();
+    assert!(cache.get(&path, (meta.len(), meta.modified().unwrap())).is_none());
+    let etag2 = rt.block_on(cache.get_or_hash(&path, &meta)).unwrap();
+    assert_ne!(etag, etag2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
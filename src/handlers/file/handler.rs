@@ -1,33 +1,39 @@
-use chrono::{offset::Local, DateTime};
-use hyper::{header, Body, Method, StatusCode};
+use hyper::{header, Body, Method, StatusCode, Version};
 
 use std::{
+    cmp::Reverse,
     fs::{self, File},
-    io,
+    io::{self, Cursor},
     net::SocketAddr,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
+use super::cache::{CachedFile, Caches, EtagCache};
 use super::ranges::{RangesForm, RangesResp};
-use super::send::send_resp;
-use crate::base::ctx::ctxs;
-use crate::base::{http, response, HeaderGetStr, Request, Response};
+use super::send::{send_resp, Compressed, NextChunk};
+use crate::base::{http, response, sendfile::SendfileQueue, HeaderGetStr, Request, Response};
 use crate::config::Route;
+use crate::consts::COMPRESS_FILE_MIN;
 use crate::contentype::guess_contentype;
-use crate::service::GlobalState;
+use crate::handlers::compress::{is_compressible, AcceptEncoding, CompressLevels, Compressor};
+use crate::service::State;
 
-use crate::handlers::exception::io_exception_handler_sync;
+use crate::handlers::conditional::{evaluate, weak_etag, with_encoding, Precondition, Validators};
+use crate::handlers::exception::{exception_handler_sync, io_exception_handler_sync};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn file_handler<'a>(
     route: &'a Route,
     reqpath: &'a str,
     path: &'a Path,
     meta: &'a fs::Metadata,
+    cache: &'a Caches,
     req: Request,
     addr: &'a SocketAddr,
-    state: GlobalState,
+    state: &'a State,
 ) -> Result<Response, http::Error> {
-    match file_handler2(route, reqpath, path, meta, state, &req, addr) {
+    match file_handler2(route, reqpath, path, meta, cache, state, &req, addr).await {
         Ok(resp) => resp,
         Err(e) => {
             error!("file_handler2 faield: {:?}", e);
@@ -36,57 +42,88 @@ pub async fn file_handler<'a>(
     }
 }
 
-// https://github.com/rust-lang/rust/issues/59001
-pub fn file_handler2(
-    _route: &Route,
+#[allow(clippy::too_many_arguments)]
+pub async fn file_handler2(
+    route: &Route,
     _reqpath: &str,
     path: &Path,
     meta: &fs::Metadata,
-    state: ctxs::State,
+    cache: &Caches,
+    state: &State,
     req: &Request,
     addr: &SocketAddr,
 ) -> io::Result<Result<Response, http::Error>> {
     let mut resp = response();
-    let cache_secs = state.config().cache_secs;
+    let cache_secs = route.cache_secs(state.config());
 
-    if cache_secs > 0 {
-        let last_modified = meta.modified()?;
-        let last_modified: DateTime<Local> = last_modified.into();
-        let http_last_modified = last_modified.to_rfc2822();
-
-        // W/"80-5d564a70.3797f8b1"
-        let etag = format!(
-            "W/\"{:x}-{:x}.{:x}\"",
-            meta.len(),
-            last_modified.timestamp_millis(),
-            last_modified.timestamp_subsec_nanos(),
-        );
-
-        let http_etag = req.headers().get_str(header::IF_NONE_MATCH);
-
-        let if_modified_since = req
-            .headers()
-            .get_str_option(header::IF_MODIFIED_SINCE)
-            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
-            .map(|v| v.with_timezone(&Local));
-
-        if etag.as_str() == http_etag
-            && if_modified_since
-                .map(|v| v.timestamp() == last_modified.timestamp())
-                .unwrap_or(true)
-        {
-            // 304
-            return Ok(resp.status(StatusCode::NOT_MODIFIED).body(Body::empty()));
+    let accept_encodings_str = req.headers().get_str(header::ACCEPT_ENCODING);
+    let rangestr = req.headers().get_str_option(header::RANGE);
+    let levels = CompressLevels::new(route, state.config());
+    let compressible = !levels.is_disabled() && meta.len() >= COMPRESS_FILE_MIN && is_compressible(path);
+
+    if route.precompressed || compressible {
+        resp = resp.header(header::VARY, "Accept-Encoding");
+    }
+    let sidecar = if route.precompressed {
+        precompressed(path, accept_encodings_str)
+    } else {
+        None
+    };
+    // on the fly, the ranges of the compressed are meaningless
+    let compressor = if sidecar.is_none() && compressible && rangestr.is_none() {
+        Compressor::new(accept_encodings_str, levels).ok()
+    } else {
+        None
+    };
+    let (file_meta, encoding) = match &sidecar {
+        Some((_, sidecar_meta, encoding)) => (sidecar_meta, Some(*encoding)),
+        None => (meta, compressor.as_ref().map(|c| c.algorithm())),
+    };
+
+    let magic_limit = route.magic_limit(state.config());
+    let cached = match &cache.files {
+        Some(cache) if sidecar.is_none() && cache.accepts(meta) => {
+            Some(cache.get_or_read(path, meta, magic_limit, route.strong_etag)?)
         }
+        _ => None,
+    };
+
+    let validators = match &cached {
+        Some(cached) => Validators::new(with_encoding(&cached.etag, encoding), cached.modified),
+        None => {
+            let served_path = sidecar.as_ref().map(|(p, _, _)| p.as_path()).unwrap_or(path);
+            validators(cache.etags.as_ref(), served_path, file_meta, encoding).await?
+        }
+    };
+    let precondition = evaluate(req, &validators);
+
+    if cache_secs > 0 {
         resp = resp.header(header::CACHE_CONTROL, format!("public, max-age={}", cache_secs).as_str());
-        resp = resp.header(header::LAST_MODIFIED, http_last_modified);
-        resp = resp.header(header::ETAG, etag);
+        resp = resp.header(header::LAST_MODIFIED, validators.http_last_modified());
+        resp = resp.header(header::ETAG, validators.etag);
     }
 
-    let mut file = File::open(path)?;
-    let mut contentype = guess_contentype(&mut file, meta, path)?;
+    let rangestr = match precondition {
+        Precondition::Proceed(range) => rangestr.filter(|_| range),
+        // 304 with the validators
+        Precondition::NotModified => return Ok(resp.status(StatusCode::NOT_MODIFIED).body(Body::empty())),
+        // 412
+        Precondition::Failed => return Ok(exception_handler_sync(412, None, req, addr)),
+    };
 
-    let rangestr = req.headers().get_str_option(header::RANGE);
+    let (mut contentype, content) = match cached {
+        Some(cached) => (cached.content_type.clone(), Content::Cached(cached)),
+        None => {
+            // the content type is the original's
+            let mut file = File::open(path)?;
+            let contentype = guess_contentype(&mut file, meta, path, magic_limit)?;
+            if let Some((sidecar_path, _, _)) = &sidecar {
+                file = File::open(sidecar_path)?;
+            }
+            (contentype, Content::File(file))
+        }
+    };
+    let meta = file_meta;
 
     let (rangesform, contentlen, contentrange) = if let Some(rangestr) = rangestr {
         match rangestr
@@ -110,8 +147,14 @@ pub fn file_handler2(
     if !contentrange.is_empty() {
         resp = resp.header(header::CONTENT_RANGE, contentrange);
     }
-    resp = resp.header(header::CONTENT_LENGTH, contentlen);
+    // chunked if compressed on the fly
+    if compressor.is_none() {
+        resp = resp.header(header::CONTENT_LENGTH, contentlen);
+    }
     resp = resp.header(header::CONTENT_TYPE, contentype);
+    if let Some(encoding) = encoding {
+        resp = resp.header(header::CONTENT_ENCODING, encoding);
+    }
     resp = resp.header(header::ACCEPT_RANGES, "bytes");
 
     debug!("{}'s ranges str: {:?}, form: {:?}", addr, rangestr, rangesform);
@@ -123,12 +166,28 @@ pub fn file_handler2(
             } else {
                 StatusCode::OK
             };
-            let body = if contentlen > 0 {
-                let (sender, body) = Body::channel();
-                state.spawn(send_resp(RangesResp::new(rangesform, file), sender, *addr));
-                body
-            } else {
-                Body::empty()
+            let body = match content {
+                _ if contentlen == 0 => Body::empty(),
+                Content::Cached(cached) => match rangesform.single().filter(|_| compressor.is_none()) {
+                    Some((start, end)) => cached.bytes.slice(start as usize..end as usize).into(),
+                    None => {
+                        let content = Cursor::new(cached.bytes.clone());
+                        ranges_body(RangesResp::new(rangesform, content), compressor, addr)?
+                    }
+                },
+                Content::File(file) => {
+                    // the HTTP/2 copies the chunks of body into its frames
+                    let sendfile = req
+                        .extensions()
+                        .get::<SendfileQueue>()
+                        .filter(|_| compressor.is_none() && req.version() < Version::HTTP_2)
+                        .and_then(|queue| rangesform.single().map(|(start, end)| (queue, start, end)));
+
+                    match sendfile {
+                        Some((queue, start, end)) => Body::wrap_stream(queue.body(file, start, end)),
+                        None => ranges_body(RangesResp::new(rangesform, file), compressor, addr)?,
+                    }
+                }
             };
 
             Ok(resp.status(code).body(body))
@@ -138,3 +197,64 @@ pub fn file_handler2(
         _ => unreachable!(),
     }
 }
+
+enum Content {
+    Cached(Arc<CachedFile>),
+    File(File),
+}
+
+// the ranges sent by a task, compressed on the fly if the compressor exists
+fn ranges_body<C>(ranges: RangesResp<C>, compressor: Option<Compressor>, addr: &SocketAddr) -> io::Result<Body>
+where
+    RangesResp<C>: NextChunk + Send + 'static,
+{
+    let (sender, body) = Body::channel();
+    match compressor {
+        Some(compressor) => {
+            let encoder = compressor.encoder()?;
+            tokio::spawn(send_resp(Compressed::new(ranges, encoder), sender, *addr))
+        }
+        None => tokio::spawn(send_resp(ranges, sender, *addr)),
+    };
+    Ok(body)
+}
+
+// W/"80-5d564a70.3797f8b1", W/"80-5d564a70.3797f8b1-gzip" for the compressed, or the strong one
+async fn validators(
+    etags: Option<&EtagCache>,
+    path: &Path,
+    meta: &fs::Metadata,
+    encoding: Option<&str>,
+) -> io::Result<Validators> {
+    let etag = match etags {
+        Some(etags) => etags.get_or_hash(path, meta).await?,
+        None => weak_etag(meta)?,
+    };
+    Ok(Validators::new(with_encoding(&etag, encoding), meta.modified()?))
+}
+
+// the smaller first
+const SIDECARS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+// `app.js.br` or `app.js.gz` next to the `app.js`, the higher q-value first, (path, metadata, encoding)
+fn precompressed(path: &Path, accept_encodings_str: &str) -> Option<(PathBuf, fs::Metadata, &'static str)> {
+    let accept = AcceptEncoding::parse(accept_encodings_str);
+    let identity = accept.identity();
+    let mut sidecars = SIDECARS
+        .iter()
+        .map(|(encoding, extension)| (accept.qvalue(encoding), encoding, extension))
+        .filter(|(q, _, _)| *q > 0 && *q >= identity)
+        .collect::<Vec<_>>();
+    sidecars.sort_by_key(|(q, _, _)| Reverse(*q));
+
+    sidecars.into_iter().find_map(|(_, encoding, extension)| {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(extension);
+        let sidecar = PathBuf::from(sidecar);
+        sidecar
+            .metadata()
+            .ok()
+            .filter(|m| m.is_file())
+            .map(|meta| (sidecar, meta, *encoding))
+    })
+}
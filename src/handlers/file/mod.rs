@@ -1,3 +1,4 @@
+pub mod cache;
 pub mod handler;
 pub mod ranges;
 pub mod send;
@@ -23,6 +23,13 @@ impl RangesForm {
     pub fn is_partail(&self) -> bool {
         self.partail
     }
+    /// [start, end) of the whole or the single range, None for the multipart
+    pub fn single(&self) -> Option<(u64, u64)> {
+        match self.ranges.as_slice() {
+            [(start, end, _)] if self.boundary == 0 => Some((*start, *end)),
+            _ => None,
+        }
+    }
     pub fn boundary_eof(&mut self) -> Option<String> {
         if self.boundary > 0 {
             let eof = format!("\r\n--{:#034x}--", self.boundary);
@@ -1,7 +1,6 @@
 use hyper::body::{Bytes, Sender};
 
 use std::{
-    fs::File,
     io::{self, Read, Seek, SeekFrom},
     mem,
     net::SocketAddr,
@@ -9,6 +8,7 @@ use std::{
 
 use super::ranges::RangesResp;
 use crate::consts::MutStatic;
+use crate::handlers::compress::Encoder;
 
 pub async fn send_resp<R>(mut resp: R, mut sender: Sender, addr: SocketAddr)
 where
@@ -49,9 +49,9 @@ pub trait NextChunk {
     fn next_chunk(&mut self) -> io::Result<Option<Bytes>>;
 }
 
-impl NextChunk for RangesResp<File> {
+impl<C: Read + Seek> NextChunk for RangesResp<C> {
     fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
-        fn inner(this: &mut RangesResp<File>, buf: &mut [u8], chunk_size: usize) -> io::Result<Option<Bytes>> {
+        fn inner<C: Read + Seek>(this: &mut RangesResp<C>, buf: &mut [u8], chunk_size: usize) -> io::Result<Option<Bytes>> {
             if this.is_empty() {
                 return Ok(this.form.boundary_eof().map(|s| s.into()));
             }
@@ -92,3 +92,39 @@ impl NextChunk for RangesResp<File> {
         BUF.with(|buf| inner(self, &mut buf.get_mut()[..], CHUNK_SIZE))
     }
 }
+
+/// Compress the chunks of `R` on the fly
+pub struct Compressed<R> {
+    inner: R,
+    encoder: Option<Encoder>,
+}
+
+impl<R> Compressed<R> {
+    pub fn new(inner: R, encoder: Encoder) -> Self {
+        Self {
+            inner,
+            encoder: Some(encoder),
+        }
+    }
+}
+
+impl<R: NextChunk> NextChunk for Compressed<R> {
+    fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            let encoder = match self.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Ok(None),
+            };
+
+            let compressed = match self.inner.next_chunk()? {
+                Some(chunk) => encoder.write(&chunk)?,
+                None => self.encoder.take().unwrap().finish()?,
+            };
+
+            // the encoder may buffer the small chunks
+            if !compressed.is_empty() {
+                return Ok(Some(compressed.into()));
+            }
+        }
+    }
+}
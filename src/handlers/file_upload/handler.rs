@@ -4,7 +4,7 @@ use std::{net::SocketAddr, path::Path};
 // use crate::base::ctx::ctxs;
 use crate::base::{http, response, Request, Response};
 use crate::config::Route;
-use crate::service::GlobalState;
+use crate::service::State;
 
 use super::multipart::MultiPart;
 
@@ -17,7 +17,7 @@ pub async fn file_upload_handler<'a>(
     path: &'a Path,
     req: Request,
     addr: &'a SocketAddr,
-    _state: GlobalState,
+    _state: &'a State,
 ) -> Result<Response, http::Error> {
     let (parts, body) = req.into_parts();
 
@@ -1,26 +1,44 @@
 use futures::FutureExt;
-use hyper::Method;
+use hyper::{
+    header::{self, HeaderValue},
+    Method,
+};
 
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    fs::Metadata,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use super::{
-    exception::{exception_handler_sync, redirect_handler_sync},
-    file::file_handler,
+    exception::{exception_handler_sync, method_not_allowed_handler_sync, redirect_handler_sync},
+    file::{cache::Caches, file_handler},
     file_upload::file_upload_handler,
     index::index_handler,
     mkdir::{method_maybe_mkdir, mkdir_handler},
 };
 
-use crate::base::{
-    ctx::{ctxs, Ctx},
-    handler::BoxedHandler,
-    http, Request, Response,
+use crate::{
+    base::{
+        ctx::{ctxs, Ctx},
+        exclude::{Excluder, Excludes},
+        handler::BoxedHandler,
+        http, Request, Response,
+    },
+    config::Route,
+    tools::url_for_path,
 };
 
-pub fn fs_handler() -> BoxedHandler {
-    use std::path::Path;
-
-    async fn fs_handler2<'a>(req: Request, addr: &'a SocketAddr, ctx: &'a mut Ctx) -> Result<Response, http::Error> {
+pub fn fs_handler(route: &Route) -> crate::how::Result<BoxedHandler> {
+    async fn fs_handler2<'a>(
+        req: Request,
+        addr: &'a SocketAddr,
+        ctx: &'a mut Ctx,
+        excludes: Arc<Excludes>,
+        cache: Arc<Caches>,
+    ) -> Result<Response, http::Error> {
+        let cache = &*cache;
         let route = ctx.get::<ctxs::Route>().unwrap();
         let reqpath = ctx.get::<ctxs::ReqPath>().unwrap();
         let reqpathcs = ctx.get::<ctxs::ReqPathCs>().unwrap();
@@ -49,9 +67,32 @@ pub fn fs_handler() -> BoxedHandler {
         } {
             meta
         } else {
+            if let Some((path, meta)) = extension_fallback(route, reqpath, reqpath_fixed, reqpathcs_remaining, &excludes) {
+                if ![Method::GET, Method::HEAD].contains(req.method()) {
+                    return method_not_allowed_handler_sync(&allow(route, &[Method::GET, Method::HEAD]), &req, addr);
+                }
+                debug!("reqpath: {}, extension fallback: {}", reqpath, path.display());
+                return file_handler(route, reqpath, &path, &meta, cache, req, addr, state).await;
+            }
+            if let Some((path, meta)) = spa_fallback(route, reqpathcs_remaining) {
+                if [Method::GET, Method::HEAD].contains(req.method()) {
+                    debug!("reqpath: {}, spa fallback: {}", reqpath, path.display());
+                    let mut resp = file_handler(route, reqpath, &path, &meta, cache, req, addr, state).await?;
+                    // the entry of the app, revalidate it to catch the new builds
+                    resp.headers_mut()
+                        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                    return Ok(resp);
+                }
+            }
             return exception_handler_sync(404, None, &req, addr);
         };
 
+        // hide it as missing
+        let excluder = match Excluder::walk(Path::new(&route.path), &excludes, reqpathcs_remaining, meta.is_dir()) {
+            Some(excluder) => excluder,
+            None => return exception_handler_sync(404, None, &req, addr),
+        };
+
         match (meta.is_dir(), meta.is_file()) {
             (true, false) => {
                 if !reqpath.ends_with('/') {
@@ -74,29 +115,24 @@ pub fn fs_handler() -> BoxedHandler {
                 };
 
                 if ![Method::GET, Method::HEAD].contains(req.method()) {
-                    return exception_handler_sync(405, None, &req, addr);
+                    return method_not_allowed_handler_sync(
+                        &allow(route, &[Method::GET, Method::HEAD, Method::POST]),
+                        &req,
+                        addr,
+                    );
                 }
 
-                if route.redirect_html {
-                    let mut file = "index.html";
-                    let mut fp = reqpath_fixed.join(file);
-
-                    let fm_is_file = move |f: PathBuf| f.metadata().map(|m| m.is_file()).unwrap_or(false);
-                    let mut fm = fm_is_file(fp);
-
-                    if !fm {
-                        file = "index.htm";
-                        fp = reqpath_fixed.join(file);
-                        fm = fm_is_file(fp);
-                    }
-
-                    if fm {
+                if route.serve_index || route.redirect_html {
+                    if let Some((file, path, meta)) = index_file(route, reqpath_fixed, &excluder) {
+                        if route.serve_index {
+                            return file_handler(route, reqpath, &path, &meta, cache, req, addr, state).await;
+                        }
                         let dest = format!("{}{}", reqpath, file);
                         return redirect_handler_sync(true, dest);
                     }
                 }
 
-                index_handler(route, reqpath, reqpath_fixed, &meta, req, addr, state).await
+                index_handler(route, reqpath, reqpath_fixed, &meta, &excluder, req, addr, state).await
             }
             (false, true) => {
                 // route is a file.. &&
@@ -105,10 +141,17 @@ pub fn fs_handler() -> BoxedHandler {
                 }
 
                 if ![Method::GET, Method::HEAD].contains(req.method()) {
-                    return exception_handler_sync(405, None, &req, addr);
+                    return method_not_allowed_handler_sync(&allow(route, &[Method::GET, Method::HEAD]), &req, addr);
                 }
 
-                file_handler(route, reqpath, reqpath_fixed, &meta, req, addr, state).await
+                if route.trim_extensions && !reqpathcs_remaining_is_empty {
+                    let query = req.uri().query();
+                    if let Some(dest) = trimmed_url(route, reqpath, query, reqpath_fixed, reqpathcs_remaining, &excludes) {
+                        return redirect_handler_sync(true, dest);
+                    }
+                }
+
+                file_handler(route, reqpath, reqpath_fixed, &meta, cache, req, addr, state).await
             }
             (d, f) => {
                 error!(
@@ -125,5 +168,127 @@ pub fn fs_handler() -> BoxedHandler {
         }
     }
 
-    Box::new(|req: Request, addr: &SocketAddr, ctx: &mut Ctx| fs_handler2(req, addr, ctx).boxed())
+    let excludes = Arc::new(Excludes::new(&route.exclude)?);
+    let cache = Arc::new(Caches::new(route));
+    Ok(Box::new(move |req: Request, addr: &SocketAddr, ctx: &mut Ctx| {
+        fs_handler2(req, addr, ctx, excludes.clone(), cache.clone()).boxed()
+    }))
+}
+
+// the methods supported by the resource and allowed by the route
+fn allow(route: &Route, supported: &[Method]) -> String {
+    let mut allow = supported
+        .iter()
+        .filter(|m| *m != Method::POST || route.upload || route.mkdir)
+        .filter(|m| {
+            route
+                .methods
+                .as_ref()
+                .map(|ms| ms.iter().any(|s| s.eq_ignore_ascii_case(m.as_str())))
+                .unwrap_or(true)
+        })
+        .map(|m| m.as_str())
+        .collect::<Vec<_>>();
+    allow.push(Method::OPTIONS.as_str());
+    allow.join(", ")
+}
+
+// the first existing and not excluded one of the route's index files
+fn index_file<'a>(route: &'a Route, dir: &Path, excluder: &Excluder) -> Option<(&'a str, PathBuf, Metadata)> {
+    route
+        .index_files()
+        .into_iter()
+        .filter(|file| !excluder.is_excluded(file, false))
+        .find_map(|file| {
+            let path = dir.join(file);
+            path.metadata().ok().filter(|m| m.is_file()).map(|meta| (file, path, meta))
+        })
+}
+
+// `/about` -> `about.html`, not for the directory url
+fn extension_fallback(
+    route: &Route,
+    reqpath: &str,
+    path: &Path,
+    reqpathcs_remaining: &[String],
+    excludes: &Excludes,
+) -> Option<(PathBuf, Metadata)> {
+    let last = reqpathcs_remaining.last()?;
+    if reqpath.ends_with('/') {
+        return None;
+    }
+
+    route.try_extensions.iter().find_map(|ext| {
+        let mut file = path.as_os_str().to_owned();
+        file.push(ext);
+        let file = PathBuf::from(file);
+        let meta = if route.follow_links {
+            file.metadata()
+        } else {
+            file.symlink_metadata()
+        };
+        let meta = meta.ok().filter(|m| m.is_file())?;
+
+        let mut cs = reqpathcs_remaining.to_vec();
+        *cs.last_mut().unwrap() = format!("{}{}", last, ext);
+        Excluder::walk(Path::new(&route.path), excludes, &cs, false)?;
+
+        Some((file, meta))
+    })
+}
+
+// `/about.html?a=b` -> `/about?a=b`, if the `/about` falls back to this file
+fn trimmed_url(
+    route: &Route,
+    reqpath: &str,
+    query: Option<&str>,
+    path: &Path,
+    reqpathcs_remaining: &[String],
+    excludes: &Excludes,
+) -> Option<String> {
+    let last = reqpathcs_remaining.last()?;
+    let file = path.to_str()?;
+
+    route
+        .try_extensions
+        .iter()
+        .filter(|ext| last.len() > ext.len() && last.ends_with(ext.as_str()))
+        .find_map(|ext| {
+            let stem = &file[..file.len() - ext.len()];
+            let reqpath = &reqpath[..reqpath.len() - ext.len()];
+            let mut cs = reqpathcs_remaining.to_vec();
+            cs.last_mut().unwrap().truncate(last.len() - ext.len());
+
+            // the `/about` is itself, or falls back to another one such as `about.html` for `about.htm`
+            if Path::new(stem).exists() {
+                return None;
+            }
+            let (fallback, _) = extension_fallback(route, reqpath, Path::new(stem), &cs, excludes)?;
+            if fallback != path {
+                return None;
+            }
+
+            let mut dest = url_for_path(reqpath);
+            if let Some(query) = query {
+                dest.push('?');
+                dest.push_str(query);
+            }
+            Some(dest)
+        })
+}
+
+// the missing assets(have extension) still 404
+fn spa_fallback(route: &Route, reqpathcs_remaining: &[String]) -> Option<(PathBuf, Metadata)> {
+    let fallback = route.spa_fallback.as_ref()?;
+    let has_extension = reqpathcs_remaining
+        .last()
+        .map(|c| Path::new(c).extension().is_some())
+        .unwrap_or_default();
+
+    if has_extension {
+        return None;
+    }
+
+    let path = Path::new(&route.path).join(fallback);
+    path.metadata().ok().filter(|m| m.is_file()).map(|meta| (path, meta))
 }
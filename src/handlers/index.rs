@@ -3,32 +3,34 @@ use hyper::{header, Body, Method, StatusCode};
 
 use std::{fs, io, net::SocketAddr, path::Path};
 
-use crate::base::ctx::ctxs;
-use crate::base::{http, response, HeaderGetStr, Request, Response};
+use crate::base::{exclude::Excluder, http, response, Request, Response};
 use crate::config::Route;
 use crate::consts::CONTENT_TYPE_HTML;
-use crate::service::GlobalState;
+use crate::service::State;
 use crate::views::{EntryMetadata, EntryOrder};
 
 use super::{
-    compress::compress_handler,
+    compress::{compress_handler, CompressLevels},
+    conditional::{evaluate, Precondition, Validators},
     exception::{exception_handler_sync, io_exception_handler_sync},
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn index_handler<'a>(
     route: &'a Route,
     reqpath: &'a str,
     path: &'a Path,
     meta: &'a fs::Metadata,
+    excluder: &'a Excluder<'a>,
     req: Request,
     addr: &'a SocketAddr,
-    state: GlobalState,
+    state: &'a State,
 ) -> Result<Response, http::Error> {
     if route.disable_index {
         return exception_handler_sync(403, Some("index(directory) view is closed"), &req, addr);
     }
 
-    match index_handler2(route, reqpath, path, meta, &req, addr, state).await {
+    match index_handler2(route, reqpath, path, meta, excluder, &req, addr, state).await {
         Ok(resp) => resp,
         Err(e) => {
             error!("index_handler2 faield: {:?}", e);
@@ -37,63 +39,55 @@ pub async fn index_handler<'a>(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn index_handler2<'a>(
     route: &'a Route,
     reqpath: &'a str,
     path: &'a Path,
     meta: &'a fs::Metadata,
+    excluder: &'a Excluder<'a>,
     req: &'a Request,
     addr: &'a SocketAddr,
-    state: ctxs::State,
+    state: &'a State,
 ) -> io::Result<Result<Response, http::Error>> {
     let mut resp = response();
 
     let entry_order = EntryOrder::new(req.uri().query());
-    let cache_secs = state.config().cache_secs;
+    let cache_secs = route.cache_secs(state.config());
+
+    let last_modified: DateTime<Local> = meta.modified()?.into();
+    // W/"80-5d564a70.3797f8b1@Empty"
+    let etag = format!(
+        "W/\"{:x}-{:x}.{:x}@{}\"",
+        meta.len(),
+        last_modified.timestamp_millis(),
+        last_modified.timestamp_subsec_nanos(),
+        entry_order
+    );
+    let validators = Validators::new(etag, last_modified.into());
 
     if cache_secs > 0 {
-        let last_modified = meta.modified()?;
-        let last_modified: DateTime<Local> = last_modified.into();
-        let http_last_modified = last_modified.to_rfc2822();
-
-        // W/"80-5d564a70.3797f8b1@Empty"
-        let etag = format!(
-            "W/\"{:x}-{:x}.{:x}@{}\"",
-            meta.len(),
-            last_modified.timestamp_millis(),
-            last_modified.timestamp_subsec_nanos(),
-            entry_order
-        );
-
-        let http_etag = req.headers().get_str(header::IF_NONE_MATCH);
-
-        let if_modified_since = req
-            .headers()
-            .get_str_option(header::IF_MODIFIED_SINCE)
-            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
-            .map(|v| v.with_timezone(&Local));
-
-        if etag.as_str() == http_etag
-            && if_modified_since
-                .map(|v| v.timestamp() == last_modified.timestamp())
-                .unwrap_or(true)
-        {
-            // 304
-            return Ok(resp.status(StatusCode::NOT_MODIFIED).body(Body::empty()));
-        }
         resp = resp.header(header::CACHE_CONTROL, format!("public, max-age={}", cache_secs).as_str());
-        resp = resp.header(header::LAST_MODIFIED, http_last_modified);
-        resp = resp.header(header::ETAG, etag);
+        resp = resp.header(header::LAST_MODIFIED, validators.http_last_modified());
+        resp = resp.header(header::ETAG, validators.etag.as_str());
+    }
+
+    match evaluate(req, &validators) {
+        Precondition::Proceed(_) => {}
+        // 304 with the validators
+        Precondition::NotModified => return Ok(resp.status(StatusCode::NOT_MODIFIED).body(Body::empty())),
+        // 412
+        Precondition::Failed => return Ok(exception_handler_sync(412, None, req, addr)),
     }
 
-    let html = render_html(addr, reqpath, path, &req, &entry_order, route)?;
+    let html = render_html(addr, reqpath, path, &req, &entry_order, route, excluder)?;
     resp = resp.header(header::CONTENT_TYPE, CONTENT_TYPE_HTML);
     resp = resp.header(header::CONTENT_LENGTH, html.len());
 
     match *req.method() {
         Method::GET => {
-            let compress_level = state.config().compress_level;
-            Ok(compress_handler(req, addr, resp, html, compress_level).await)
+            let levels = CompressLevels::new(route, state.config());
+            Ok(compress_handler(req, addr, resp, html, levels).await)
         }
         // 204： curl -Lv -X HEAD "0.0.0.0:8000/src/main.rs"
         Method::HEAD => Ok(resp.status(StatusCode::NO_CONTENT).body(Body::empty())),
@@ -101,6 +95,7 @@ pub async fn index_handler2<'a>(
     }
 }
 
+use super::exception::server_addr;
 use crate::tools::url_for_parent;
 use crate::views::IndexTemplate;
 use askama::Template;
@@ -112,16 +107,19 @@ pub fn render_html(
     req: &Request,
     order: &EntryOrder,
     config: &Route,
+    excluder: &Excluder,
 ) -> io::Result<String> {
-    let metadatas = EntryMetadata::read_dir(index, config.follow_links, config.show_hider, order)?;
+    let metadatas = EntryMetadata::read_dir(index, config.follow_links, config.show_hider, excluder, order)?;
     let next_order = order.next();
     let parent = url_for_parent(req.uri().path());
+    let server = server_addr(req);
 
     let template = IndexTemplate::new(
         title,
         title,
         &parent,
         &remote_addr,
+        &server,
         next_order,
         metadatas.iter(),
         config.upload,
@@ -2,7 +2,7 @@ use std::{fs, net::SocketAddr, path::Path, str};
 
 use crate::base::{body_to_bytes, http, response, Body, Request, Response};
 use crate::config::Route;
-use crate::service::GlobalState;
+use crate::service::State;
 
 // .to_lowercase()
 pub fn method_maybe_mkdir(req: &Request) -> bool {
@@ -24,7 +24,7 @@ pub async fn mkdir_handler<'a>(
     path: &'a Path,
     req: Request,
     addr: &'a SocketAddr,
-    _state: GlobalState,
+    _state: &'a State,
 ) -> Result<Response, http::Error> {
     let f = |code: u16, s: &'static str| response().status(code).body(s.into());
 
@@ -1,4 +1,5 @@
 pub mod compress;
+pub mod conditional;
 pub mod exception;
 pub mod file;
 pub mod file_upload;
@@ -6,7 +7,9 @@ pub mod filesystem;
 pub mod index;
 pub mod mkdir;
 pub mod proxy;
+pub mod upstream;
 
 pub use exception::notfound_handler;
 pub use filesystem::fs_handler;
 pub use proxy::{method_maybe_proxy, proxy_handler};
+pub use upstream::upstream_handler;
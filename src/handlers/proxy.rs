@@ -27,15 +27,15 @@ pub fn method_maybe_proxy(req: &Request) -> Option<bool> {
     }
 }
 
-pub fn proxy_handler(path: &str) -> BoxedHandler {
-    let reg = Regex::new(path).expect("proxy_handler<'a>().Regex::new");
+pub fn proxy_handler(path: &str) -> crate::how::Result<BoxedHandler> {
+    let reg = Regex::new(path).map_err(|e| format_err!("invalid regex for proxy({}): {}", path, e))?;
     let client = Client::builder()
         .use_rustls_tls()
         .timeout(Duration::from_secs(10))
         .build()
         .expect("Client::builder().use_rustls_tls().build()");
 
-    Box::new(move |req: Request, addr: &SocketAddr, ctx: &mut Ctx| {
+    Ok(Box::new(move |req: Request, addr: &SocketAddr, ctx: &mut Ctx| {
         proxy_handler2(
             unsafe { std::mem::transmute(&reg) },
             unsafe { std::mem::transmute(&client) },
@@ -44,7 +44,7 @@ pub fn proxy_handler(path: &str) -> BoxedHandler {
             ctx,
         )
         .boxed()
-    })
+    }))
 }
 
 // timeout:
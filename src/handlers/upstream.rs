@@ -0,0 +1,510 @@
+use bytesize::ByteSize;
+use futures::{future, FutureExt};
+use hyper::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    upgrade::Upgraded,
+    Body, StatusCode,
+};
+use reqwest::{redirect::Policy, Client, Url};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    task, time,
+};
+use tokio_rustls::{rustls::ClientConfig, webpki::DNSNameRef, TlsConnector};
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use super::exception::exception_handler_sync;
+use crate::{
+    base::{
+        ctx::{ctxs, Ctx},
+        forwarded::parse_node,
+        handler::BoxedHandler,
+        http, response, PeerAddr, Request, Response, ServerAddr,
+    },
+    tools::url_component_encode,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HEAD_LIMIT: usize = 16 * 1024;
+
+// https://tools.ietf.org/html/rfc7230#section-6.1
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+lazy_static! {
+    // for the upgrades of https upstream
+    static ref TLS_CONNECTOR: TlsConnector = {
+        let mut config = ClientConfig::new();
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+        TlsConnector::from(Arc::new(config))
+    };
+}
+
+/// The upstream of the route in `Ctx` is used, it may contains the captures of pattern route
+pub fn upstream_handler(upstream: &str) -> crate::how::Result<BoxedHandler> {
+    if !upstream.contains('{') {
+        upstream_base(upstream)?;
+    }
+
+    // per route, the pooled connections belong to the runtime of server.
+    // unlike the client of forward proxy, the redirects and the compressed bodies are passed to the client as is,
+    // and no total timeout for the long responses
+    let client = Client::builder()
+        .use_rustls_tls()
+        .no_gzip()
+        .redirect(Policy::none())
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| format_err!("upstream({})'s client build failed: {}", upstream, e))?;
+
+    Ok(Box::new(move |req: Request, addr: &SocketAddr, ctx: &mut Ctx| {
+        upstream_handler2(client.clone(), req, addr, ctx).boxed()
+    }))
+}
+
+async fn upstream_handler2<'a>(
+    client: Client,
+    req: Request,
+    addr: &'a SocketAddr,
+    ctx: &'a mut Ctx,
+) -> Result<Response, http::Error> {
+    let route = ctx.get::<ctxs::Route>().unwrap();
+    let reqpath = ctx.get::<ctxs::ReqPath>().unwrap();
+    let reqpathcs = ctx.get::<ctxs::ReqPathCs>().unwrap();
+    let state = ctx.get::<ctxs::State>().unwrap();
+
+    let upstream = route.upstream.as_ref().expect("route.upstream");
+    let url = match upstream_url(upstream, reqpathcs, route.urlcs, reqpath.ends_with('/'), req.uri().query()) {
+        Ok(url) => url,
+        Err(e) => {
+            error!("[{} -> {}] upstream invalid: {}", addr, upstream, e);
+            return exception_handler_sync(502, None, &req, addr);
+        }
+    };
+    debug!("reqpath: {}, upstream: {}", reqpath, url);
+
+    let (peer, trusted) = req
+        .extensions()
+        .get::<PeerAddr>()
+        .map(|p| (p.addr, p.trusted))
+        .unwrap_or((*addr, false));
+    let proto = if state.config().cert.is_some() { "https" } else { "http" };
+    let upgrade = req.headers().get(header::UPGRADE).cloned();
+
+    let mut headers = req.headers().clone();
+    remove_hop_by_hop(&mut headers);
+    forwarded_headers(&mut headers, req.headers(), addr, &peer, trusted, proto);
+    // the credentials of fht2p
+    if route.authorized {
+        headers.remove(header::AUTHORIZATION);
+    }
+
+    if let Some(upgrade) = upgrade {
+        headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(header::UPGRADE, upgrade);
+        upgrade_upstream(url, headers, req, addr).await
+    } else {
+        forward_upstream(&client, url, headers, req, addr).await
+    }
+}
+
+// `http://host:port` or `http://host:port/prefix/`
+fn upstream_base(upstream: &str) -> crate::how::Result<(Url, bool)> {
+    let url = Url::parse(upstream).map_err(|e| format_err!("upstream({}) invalid: {}", upstream, e))?;
+    if !["http", "https"].contains(&url.scheme()) || url.host_str().is_none() {
+        return Err(format_err!("upstream({}) should be http(s)://host[:port][/prefix]", upstream));
+    }
+
+    // Url always has a path
+    let has_path = upstream.split_once("://").map(|(_, s)| s.contains('/')).unwrap_or_default();
+
+    Ok((url, has_path))
+}
+
+/// The url of route is replaced by the path of upstream, or kept if the upstream has no path
+fn upstream_url(
+    upstream: &str,
+    reqpathcs: &[String],
+    urlcs: usize,
+    trailing_slash: bool,
+    query: Option<&str>,
+) -> crate::how::Result<Url> {
+    let (mut url, has_path) = upstream_base(upstream)?;
+
+    let (mut path, cs) = if has_path {
+        (url.path().trim_end_matches('/').to_owned(), &reqpathcs[urlcs..])
+    } else {
+        (String::new(), reqpathcs)
+    };
+    for c in cs {
+        path.push('/');
+        path.push_str(&url_component_encode(c).to_string());
+    }
+    if path.is_empty() || trailing_slash && !path.ends_with('/') {
+        path.push('/');
+    }
+
+    url.set_path(&path);
+    url.set_query(query);
+    Ok(url)
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| name.trim().parse::<HeaderName>().ok())
+        .collect::<Vec<_>>();
+
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP.iter() {
+        headers.remove(*name);
+    }
+}
+
+/// The hops told by a trusted peer are kept from the resolved client, `X-Forwarded-For: client, proxy, peer`,
+/// or only the peer
+fn forwarded_headers(
+    headers: &mut HeaderMap,
+    origin: &HeaderMap,
+    client: &SocketAddr,
+    peer: &SocketAddr,
+    trusted: bool,
+    proto: &str,
+) {
+    let x_forwarded_for = HeaderName::from_static("x-forwarded-for");
+
+    let mut hops = vec![];
+    if trusted {
+        let inbound = origin
+            .get_all(&x_forwarded_for)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|hop| hop.trim())
+            .collect::<Vec<_>>();
+        // the ones before the client are told by the untrusted
+        match inbound
+            .iter()
+            .rposition(|hop| parse_node(hop).map(|a| a.ip()) == Some(client.ip()))
+        {
+            Some(idx) => hops.extend(inbound[idx..].iter().map(|hop| (*hop).to_owned())),
+            None if client != peer => hops.push(client.ip().to_string()),
+            None => {}
+        }
+    } else {
+        headers.remove(header::FORWARDED);
+        headers.remove(HeaderName::from_static("x-real-ip"));
+    }
+    hops.push(peer.ip().to_string());
+
+    headers.insert(x_forwarded_for, hops.join(", ").parse().expect("X-Forwarded-For"));
+    headers.insert(
+        HeaderName::from_static("x-forwarded-proto"),
+        HeaderValue::from_static(if proto == "https" { "https" } else { "http" }),
+    );
+    // the client sets the Host of upstream
+    if let Some(host) = headers.remove(header::HOST) {
+        headers.insert(HeaderName::from_static("x-forwarded-host"), host);
+    }
+}
+
+// for the error pages after the request is consumed
+fn request_shadow(req: &Request) -> Request {
+    let mut shadow = Request::new(Body::empty());
+    *shadow.uri_mut() = req.uri().clone();
+    if let Some(server) = req.extensions().get::<ServerAddr>() {
+        shadow.extensions_mut().insert(*server);
+    }
+    shadow
+}
+
+async fn forward_upstream(
+    client: &Client,
+    url: Url,
+    headers: HeaderMap,
+    req: Request,
+    addr: &SocketAddr,
+) -> Result<Response, http::Error> {
+    let shadow = request_shadow(&req);
+    let has_body = headers.contains_key(header::CONTENT_LENGTH) || req.headers().contains_key(header::TRANSFER_ENCODING);
+
+    let (parts, body) = req.into_parts();
+    let mut req2 = reqwest::Request::new(parts.method, url);
+    *req2.headers_mut() = headers;
+    if has_body {
+        *req2.body_mut() = Some(reqwest::Body::wrap_stream(body));
+    }
+
+    let resp2 = match client.execute(req2).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("[{} -> {}] upstream error: {}", addr, shadow.uri(), e);
+            let code = if e.is_timeout() { 504 } else { 502 };
+            return exception_handler_sync(code, None, &shadow, addr);
+        }
+    };
+
+    let mut resp = response().status(resp2.status());
+    let resp_headers = resp.headers_mut().expect("resp.headers_mut()");
+    *resp_headers = resp2.headers().clone();
+    remove_hop_by_hop(resp_headers);
+
+    resp.body(Body::wrap_stream(resp2.bytes_stream()))
+}
+
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+async fn connect(url: &Url) -> io::Result<Box<dyn AsyncStream>> {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let tcp = TcpStream::connect((host.trim_start_matches('[').trim_end_matches(']'), port)).await?;
+
+    if url.scheme() == "https" {
+        let dns = DNSNameRef::try_from_ascii_str(host)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid dns name: {}", host)))?;
+        let tls = TLS_CONNECTOR.connect(dns, tcp).await?;
+        Ok(Box::new(tls))
+    } else {
+        Ok(Box::new(tcp))
+    }
+}
+
+// send the request head and read the response head of upstream, the remaining bytes are returned
+async fn handshake(
+    stream: &mut Box<dyn AsyncStream>,
+    url: &Url,
+    method: &str,
+    headers: &HeaderMap,
+) -> crate::how::Result<(Response, Vec<u8>)> {
+    let mut head = Vec::with_capacity(1024);
+    head.extend_from_slice(format!("{} {}", method, url.path()).as_bytes());
+    if let Some(query) = url.query() {
+        head.extend_from_slice(format!("?{}", query).as_bytes());
+    }
+    head.extend_from_slice(b" HTTP/1.1\r\nHost: ");
+    head.extend_from_slice(url.host_str().unwrap_or_default().as_bytes());
+    if let Some(port) = url.port() {
+        head.extend_from_slice(format!(":{}", port).as_bytes());
+    }
+    head.extend_from_slice(b"\r\n");
+    for (k, v) in headers {
+        head.extend_from_slice(k.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(v.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    stream.write_all(&head).await?;
+
+    let mut buf = Vec::with_capacity(1024);
+    loop {
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(format_err!("upstream closed before the response head"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut resp2 = httparse::Response::new(&mut headers);
+        if let httparse::Status::Complete(len) = resp2.parse(&buf)? {
+            let mut resp = response().status(resp2.code.unwrap_or_default());
+            for h in resp2.headers.iter() {
+                resp = resp.header(h.name, h.value);
+            }
+            return Ok((resp.body(Body::empty())?, buf.split_off(len)));
+        }
+        if buf.len() > HEAD_LIMIT {
+            return Err(format_err!("the response head of upstream is too large"));
+        }
+    }
+}
+
+// WebSocket and the other upgrades, reqwest can't take over the connection
+async fn upgrade_upstream(url: Url, headers: HeaderMap, req: Request, addr: &SocketAddr) -> Result<Response, http::Error> {
+    let connected = time::timeout(CONNECT_TIMEOUT, async {
+        let mut stream = connect(&url).await?;
+        let (resp, remaining) = handshake(&mut stream, &url, req.method().as_str(), &headers).await?;
+        Ok::<_, crate::how::Error>((stream, resp, remaining))
+    })
+    .await;
+
+    let (stream, mut resp, remaining) = match connected {
+        Ok(Ok(connected)) => connected,
+        Ok(Err(e)) => {
+            error!("[{} -> {}] upgrade error: {}", addr, url, e);
+            return exception_handler_sync(502, None, &req, addr);
+        }
+        Err(e) => {
+            error!("[{} -> {}] upgrade error: {}", addr, url, e);
+            return exception_handler_sync(504, None, &req, addr);
+        }
+    };
+
+    if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+        warn!("[{} -> {}] upgrade refused: {}", addr, url, resp.status());
+        // the body is dropped
+        remove_hop_by_hop(resp.headers_mut());
+        resp.headers_mut().remove(header::CONTENT_LENGTH);
+        return Ok(resp);
+    }
+
+    let addr = *addr;
+    task::spawn(async move {
+        match req.into_body().on_upgrade().await {
+            Ok(upgraded) => tunnel(upgraded, stream, remaining, &addr, &url).await,
+            Err(e) => error!("[{} -> {}] upgrade error: {}", addr, url, e),
+        }
+    });
+
+    Ok(resp)
+}
+
+async fn tunnel(mut upgraded: Upgraded, stream: Box<dyn AsyncStream>, remaining: Vec<u8>, addr: &SocketAddr, url: &Url) {
+    if let Err(e) = upgraded.write_all(&remaining).await {
+        error!("[{} -> {}] tunnel error: {}", addr, url, e);
+        return;
+    }
+
+    let (mut client_r, mut client_w) = io::split(upgraded);
+    let (mut upstream_r, mut upstream_w) = io::split(stream);
+
+    // close the other side after one side finished
+    let upload = async {
+        let n = io::copy(&mut client_r, &mut upstream_w).await?;
+        upstream_w.shutdown().await.map(|_| n)
+    };
+    let download = async {
+        let n = io::copy(&mut upstream_r, &mut client_w).await?;
+        client_w.shutdown().await.map(|_| n)
+    };
+
+    match future::try_join(upload, download).await {
+        Ok((upbs, downbs)) => info!(
+            "[{} -> {}] tunnel finish: up: {}, down: {}",
+            addr,
+            url,
+            ByteSize::b(upbs).to_string_as(true),
+            ByteSize::b(downbs).to_string_as(true),
+        ),
+        Err(e) => error!("[{} -> {}] tunnel error: {}", addr, url, e),
+    }
+}
+
+#[test]
+fn upstream_url_test() {
+    let cs = |s: &str| {
+        s.split('/')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_owned())
+            .collect::<Vec<_>>()
+    };
+    let url = |upstream, reqpath: &str, urlcs, query| {
+        upstream_url(upstream, &cs(reqpath), urlcs, reqpath.ends_with('/'), query)
+            .unwrap()
+            .to_string()
+    };
+
+    // the prefix kept
+    assert_eq!(
+        url("http://127.0.0.1:3000", "/api/v1/users", 1, None),
+        "http://127.0.0.1:3000/api/v1/users"
+    );
+    // the prefix stripped
+    assert_eq!(
+        url("http://127.0.0.1:3000/", "/api/v1/users", 1, None),
+        "http://127.0.0.1:3000/v1/users"
+    );
+    assert_eq!(url("http://127.0.0.1:3000/", "/api/", 1, None), "http://127.0.0.1:3000/");
+    assert_eq!(url("http://127.0.0.1:3000/", "/api", 1, None), "http://127.0.0.1:3000/");
+    // the prefix rewrited
+    assert_eq!(
+        url("https://example.com/backend/", "/api/v1/", 1, Some("a=1&b")),
+        "https://example.com/backend/v1/?a=1&b"
+    );
+    assert_eq!(
+        url("http://[::1]:3000/x", "/a b/c?d", 0, None),
+        "http://[::1]:3000/x/a%20b/c%3Fd"
+    );
+
+    assert!(upstream_base("ftp://127.0.0.1").is_err());
+    assert!(upstream_base("127.0.0.1:3000").is_err());
+    assert!(upstream_base("http://").is_err());
+}
+
+#[test]
+fn hop_by_hop_test() {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONNECTION, "keep-alive, x-secret".parse().unwrap());
+    headers.insert("x-secret", "1".parse().unwrap());
+    headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+    headers.insert(header::HOST, "example.com".parse().unwrap());
+    headers.insert("x-forwarded-for", "1.1.1.1".parse().unwrap());
+    let origin = headers.clone();
+
+    let peer = "10.0.0.1:1234".parse().unwrap();
+    remove_hop_by_hop(&mut headers);
+    forwarded_headers(&mut headers, &origin, &"1.1.1.1:0".parse().unwrap(), &peer, true, "https");
+
+    assert!(!headers.contains_key(header::CONNECTION));
+    assert!(!headers.contains_key(header::UPGRADE));
+    assert!(!headers.contains_key("x-secret"));
+    assert!(!headers.contains_key(header::HOST));
+    assert_eq!(headers["x-forwarded-for"], "1.1.1.1, 10.0.0.1");
+    assert_eq!(headers["x-forwarded-host"], "example.com");
+    assert_eq!(headers["x-forwarded-proto"], "https");
+}
+
+#[test]
+fn forwarded_for_test() {
+    let forwarded_for = |xff: &str, client: &str, peer: &str, trusted: bool| {
+        let mut origin = HeaderMap::new();
+        origin.insert("x-forwarded-for", xff.parse().unwrap());
+        origin.insert("x-real-ip", "6.6.6.6".parse().unwrap());
+        let mut headers = origin.clone();
+        forwarded_headers(
+            &mut headers,
+            &origin,
+            &client.parse().unwrap(),
+            &peer.parse().unwrap(),
+            trusted,
+            "http",
+        );
+        (headers["x-forwarded-for"].clone(), headers.contains_key("x-real-ip"))
+    };
+
+    // spoofed by the untrusted
+    assert_eq!(
+        forwarded_for("6.6.6.6", "192.168.1.1:5000", "192.168.1.1:5000", false),
+        ("192.168.1.1".parse().unwrap(), false)
+    );
+    // the hops before the client resolved are dropped
+    assert_eq!(
+        forwarded_for("6.6.6.6, 203.0.113.1, 10.1.1.1", "203.0.113.1:0", "127.0.0.1:80", true),
+        ("203.0.113.1, 10.1.1.1, 127.0.0.1".parse().unwrap(), true)
+    );
+    // from the X-Real-IP or Forwarded
+    assert_eq!(
+        forwarded_for("unknown", "203.0.113.1:0", "127.0.0.1:80", true).0,
+        "203.0.113.1, 127.0.0.1"
+    );
+    assert_eq!(forwarded_for("unknown", "127.0.0.1:80", "127.0.0.1:80", true).0, "127.0.0.1");
+}
@@ -25,6 +25,7 @@ pub mod base;
 pub mod config;
 pub mod consts;
 pub mod contentype;
+pub mod daemon;
 pub mod handlers;
 pub mod logger;
 pub mod middlewares;
@@ -27,7 +27,7 @@ pub fn log_enabled_info(target: &str) -> bool {
     log_enabled!(target: target, Info)
 }
 
-pub fn logger_init(verbose: u64) -> JoinHandle {
+pub fn logger_init(verbose: u64, color: bool) -> JoinHandle {
     let pkg = crate::consts::NAME;
     let log = match verbose {
         0 => LevelFilter::Warn,
@@ -42,7 +42,7 @@ pub fn logger_init(verbose: u64) -> JoinHandle {
 
     let formater = BaseFormater::new()
         .local(true)
-        .color(true)
+        .color(color)
         .level(4)
         .formater(format(verbose > 1));
 
@@ -2,7 +2,7 @@
 pub extern crate nonblock_logger;
 pub extern crate fht2plib;
 
-use fht2plib::{args, process_exit, service::Service};
+use fht2plib::{args, daemon::notify_startup, process_exit, service::Service};
 
 fn main() {
     let (config, mut handle) = args::parse();
@@ -11,6 +11,7 @@ fn main() {
 
     if let Err(e) = config.startup() {
         error!("{}", e);
+        notify_startup(Err(&e));
         handle.join();
         process_exit(1)
     }
@@ -0,0 +1,162 @@
+use futures::future::{BoxFuture, FutureExt};
+use hyper::{header, StatusCode};
+use tokio::task::spawn_blocking;
+
+use std::{
+    collections::BTreeMap as Map,
+    fs::{self, File},
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    base::{
+        ctx::{ctxs, Ctx},
+        middleware::AsyncMiddleWare,
+        Response,
+    },
+    contentype::guess_contentype,
+    handlers::exception::ErrorPage,
+};
+
+/// status(`404`) or class(`4xx`) -> file
+#[derive(Debug, Default, Clone)]
+struct Pages(Map<String, String>);
+
+impl Pages {
+    fn new(pages: &Map<String, String>) -> crate::how::Result<Self> {
+        pages
+            .iter()
+            .map(|(key, file)| {
+                let key = key.to_ascii_lowercase();
+                let bs = key.as_bytes();
+                let valid = bs.len() == 3
+                    && (b'1'..=b'5').contains(&bs[0])
+                    && (bs[1..].iter().all(u8::is_ascii_digit) || &bs[1..] == b"xx");
+                if !valid {
+                    return Err(format_err!("errorPages's key({:?}) invalid, should be like 404 or 5xx", key));
+                }
+                Ok((key, file.clone()))
+            })
+            .collect::<crate::how::Result<_>>()
+            .map(Pages)
+    }
+
+    // the status first, then its class
+    fn find(&self, status: StatusCode) -> Option<&str> {
+        let code = status.as_str();
+        self.0
+            .get(code)
+            .or_else(|| self.0.get(&format!("{}xx", &code[..1])))
+            .map(|f| f.as_str())
+    }
+}
+
+/// Replace the built-in error pages with the configured files, keep the status
+#[derive(Debug, Default, Clone)]
+pub struct ErrorPages {
+    // relative to the path of route
+    route: Pages,
+    global: Pages,
+    // the global one leaves the matched requests to the ErrorPages of route
+    is_global: bool,
+}
+
+impl ErrorPages {
+    pub fn global(pages: &Map<String, String>) -> crate::how::Result<Self> {
+        Ok(Self {
+            route: Pages::default(),
+            global: Pages::new(pages)?,
+            is_global: true,
+        })
+    }
+
+    pub fn for_route(global: &Map<String, String>, route: &Map<String, String>) -> crate::how::Result<Self> {
+        Ok(Self {
+            route: Pages::new(route)?,
+            global: Pages::new(global)?,
+            is_global: false,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.route.0.is_empty() && self.global.0.is_empty()
+    }
+
+    fn find(&self, status: StatusCode, route_path: Option<&str>) -> Option<PathBuf> {
+        route_path
+            .and_then(|path| self.route.find(status).map(|f| Path::new(path).join(f)))
+            .or_else(|| self.global.find(status).map(PathBuf::from))
+    }
+}
+
+impl AsyncMiddleWare for ErrorPages {
+    fn after<'a>(&'a self, resp: &'a mut Response, _addr: &'a SocketAddr, ctx: &'a mut Ctx) -> BoxFuture<'a, ()> {
+        async move {
+            if resp.extensions().get::<ErrorPage>().is_none() {
+                return;
+            }
+            let route = ctx.get::<ctxs::Route>();
+            if self.is_global && route.is_some() {
+                return;
+            }
+
+            let path = match self.find(resp.status(), route.map(|r| r.path.as_str())) {
+                Some(path) => path,
+                None => return,
+            };
+            let config = ctx.get::<ctxs::State>().unwrap().config();
+            let magic_limit = route.map(|r| r.magic_limit(config)).unwrap_or(config.magic_limit);
+
+            // fallback to the built-in one
+            match spawn_blocking(move || read_page(&path, magic_limit).map_err(|e| (path, e))).await {
+                Ok(Ok((content_type, bytes))) => {
+                    let headers = resp.headers_mut();
+                    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+                    headers.insert(header::CONTENT_LENGTH, bytes.len().into());
+                    resp.extensions_mut().remove::<ErrorPage>();
+                    *resp.body_mut() = bytes.into();
+                }
+                Ok(Err((path, e))) => warn!("read error page {:?} failed: {}", path, e),
+                Err(e) => error!("read error page spawn_blocking failed: {}", e),
+            }
+        }
+        .boxed()
+    }
+}
+
+// (content_type, content)
+fn read_page(path: &Path, magic_limit: u64) -> io::Result<(String, Vec<u8>)> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let content_type = guess_contentype(&mut file, &metadata, path, magic_limit)?;
+    fs::read(path).map(|bytes| (content_type, bytes))
+}
+
+#[test]
+fn error_pages_test() {
+    let pages = |s: &str| json5::from_str::<Map<String, String>>(s).unwrap();
+    let global = pages(r#"{ "404": "404.html", "5XX": "error.html" }"#);
+    let route = pages(r#"{ "4xx": "4xx.html" }"#);
+    let find = |ep: &ErrorPages, code: u16, route_path: Option<&str>| {
+        ep.find(StatusCode::from_u16(code).unwrap(), route_path)
+            .map(|p| p.to_string_lossy().into_owned())
+    };
+
+    let ep = ErrorPages::global(&global).unwrap();
+    assert_eq!(find(&ep, 404, None).as_deref(), Some("404.html"));
+    assert_eq!(find(&ep, 503, None).as_deref(), Some("error.html"));
+    assert_eq!(find(&ep, 403, None), None);
+
+    let ep = ErrorPages::for_route(&global, &route).unwrap();
+    assert_eq!(find(&ep, 404, Some("www")).as_deref(), Some("www/4xx.html"));
+    assert_eq!(find(&ep, 500, Some("www")).as_deref(), Some("error.html"));
+    assert!(!ep.is_empty());
+    assert!(ErrorPages::for_route(&Map::new(), &Map::new()).unwrap().is_empty());
+
+    for key in &["40", "4044", "600", "4x", "4xy", "abc", ""] {
+        let invalid = pages(&format!(r#"{{ "{}": "a.html" }}"#, key));
+        assert!(ErrorPages::global(&invalid).is_err(), "{}", key);
+    }
+}
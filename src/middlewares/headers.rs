@@ -0,0 +1,164 @@
+use globset::{Glob, GlobMatcher};
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    StatusCode,
+};
+
+use std::net::SocketAddr;
+
+use crate::{
+    base::{
+        ctx::{ctxs, Ctx},
+        middleware::MiddleWare,
+        Response,
+    },
+    config::HeaderRule,
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: HeaderName,
+    value: HeaderValue,
+    status: Vec<StatusCode>,
+    files: Option<GlobMatcher>,
+}
+
+impl Rule {
+    fn is_match(&self, status: StatusCode, file_name: &str) -> bool {
+        (self.status.is_empty() || self.status.contains(&status))
+            && self.files.as_ref().map(|g| g.is_match(file_name)).unwrap_or(true)
+    }
+}
+
+/// Add the configured headers to responses, include the error pages
+#[derive(Debug, Default, Clone)]
+pub struct HeadersSetter {
+    rules: Vec<Rule>,
+    // the global one leaves the matched requests to the HeadersSetter of route
+    is_global: bool,
+}
+
+impl HeadersSetter {
+    pub fn new(headers: &[HeaderRule]) -> crate::how::Result<Self> {
+        let rules = headers
+            .iter()
+            .map(|rule| {
+                let name = &rule.name;
+                let status = rule
+                    .status
+                    .iter()
+                    .map(|code| StatusCode::from_u16(*code))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format_err!("header({})'s status invalid: {}", name, e))?;
+                let files = match &rule.files {
+                    Some(glob) => Some(
+                        Glob::new(glob)
+                            .map_err(|e| format_err!("header({})'s files invalid: {}", name, e))?
+                            .compile_matcher(),
+                    ),
+                    None => None,
+                };
+
+                Ok(Rule {
+                    name: name.parse().map_err(|e| format_err!("header({}) invalid: {}", name, e))?,
+                    value: rule
+                        .value
+                        .parse()
+                        .map_err(|e| format_err!("header({})'s value invalid: {}", name, e))?,
+                    status,
+                    files,
+                })
+            })
+            .collect::<crate::how::Result<_>>()?;
+
+        Ok(Self { rules, is_global: false })
+    }
+
+    pub fn global(headers: &[HeaderRule]) -> crate::how::Result<Self> {
+        Self::new(headers).map(|mut hs| {
+            hs.is_global = true;
+            hs
+        })
+    }
+
+    /// The headers of route after the global ones, override them if both matched
+    pub fn for_route(global: &[HeaderRule], route: &[HeaderRule]) -> crate::how::Result<Self> {
+        let mut rules = Self::new(global)?.rules;
+        rules.extend(Self::new(route)?.rules);
+
+        Ok(Self { rules, is_global: false })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl MiddleWare for HeadersSetter {
+    fn after(&self, resp: &mut Response, _addr: &SocketAddr, ctx: &mut Ctx) {
+        if self.is_global && ctx.get::<ctxs::Route>().is_some() {
+            return;
+        }
+
+        let status = resp.status();
+        let reqpath = ctx.get::<ctxs::ReqPath>().map(|p| p.as_str()).unwrap_or_default();
+        let file_name = reqpath.rsplit('/').next().unwrap_or_default();
+
+        for rule in self.rules.iter().filter(|r| r.is_match(status, file_name)) {
+            resp.headers_mut().insert(rule.name.clone(), rule.value.clone());
+        }
+    }
+}
+
+#[test]
+fn headers_setter_test() {
+    let rules = json5::from_str::<Vec<HeaderRule>>(
+        r#"[
+            { "name": "X-Robots-Tag", "value": "noindex" },
+            { "name": "Cache-Control", "value": "immutable", "files": "*.{js,css}", "status": [200] },
+            { "name": "Cache-Control", "value": "no-cache", "files": "*.html" },
+        ]"#,
+    )
+    .unwrap();
+    let matched = |setter: &HeadersSetter, status: u16, file_name: &str| {
+        setter
+            .rules
+            .iter()
+            .filter(|r| r.is_match(StatusCode::from_u16(status).unwrap(), file_name))
+            .map(|r| format!("{}: {}", r.name, r.value.to_str().unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    let setter = HeadersSetter::new(&rules).unwrap();
+    assert_eq!(
+        matched(&setter, 200, "app.js"),
+        vec!["x-robots-tag: noindex", "cache-control: immutable"]
+    );
+    assert_eq!(matched(&setter, 404, "app.js"), vec!["x-robots-tag: noindex"]);
+    assert_eq!(
+        matched(&setter, 200, "index.html"),
+        vec!["x-robots-tag: noindex", "cache-control: no-cache"]
+    );
+
+    let route = json5::from_str::<Vec<HeaderRule>>(r#"[{ "name": "x-robots-tag", "value": "all" }]"#).unwrap();
+    let merged = HeadersSetter::for_route(&rules, &route).unwrap();
+    assert_eq!(
+        matched(&merged, 200, "index.html"),
+        vec!["x-robots-tag: noindex", "cache-control: no-cache", "x-robots-tag: all"]
+    );
+
+    // the later matched wins
+    let mut resp = Response::default();
+    *resp.status_mut() = StatusCode::OK;
+    let mut ctx = Ctx::new();
+    ctx.insert::<ctxs::ReqPath>("/index.html".to_owned());
+    MiddleWare::after(&merged, &mut resp, &"127.0.0.1:80".parse().unwrap(), &mut ctx);
+    assert_eq!(resp.headers()["x-robots-tag"], "all");
+    assert_eq!(resp.headers()["cache-control"], "no-cache");
+
+    let invalid = |s: &str| HeadersSetter::new(&json5::from_str::<Vec<HeaderRule>>(s).unwrap()).is_err();
+    assert!(invalid(r#"[{ "name": "X Bad", "value": "1" }]"#));
+    assert!(invalid(r#"[{ "name": "X-Ok", "value": "\n" }]"#));
+    assert!(invalid(r#"[{ "name": "X-Ok", "value": "1", "status": [1000] }]"#));
+    assert!(invalid(r#"[{ "name": "X-Ok", "value": "1", "files": "a[" }]"#));
+}
@@ -0,0 +1,107 @@
+use hyper::{
+    header::{self, HeaderValue},
+    Method, StatusCode,
+};
+
+use std::net::SocketAddr;
+
+use crate::{
+    base::{ctx::Ctx, middleware::MiddleWare, response, Request, Response},
+    config::Route,
+    handlers::exception::method_not_allowed_handler_sync,
+};
+
+/// Refuse the methods not in the `methods` of route with 405, answer `OPTIONS` with the `Allow`
+#[derive(Debug, Clone)]
+pub struct MethodsFilter {
+    methods: Vec<Method>,
+    // like `GET, HEAD, OPTIONS`
+    allow: HeaderValue,
+}
+
+impl MethodsFilter {
+    /// The `methods` of route, or the ones its handler supports, None for the upstream accepts all
+    pub fn for_route(route: &Route) -> crate::how::Result<Option<Self>> {
+        // the POST of the route without upload and mkdir is refused by the fs_handler with 403
+        let allow_post = route.methods.is_some() || route.upload || route.mkdir;
+        let methods = match (&route.methods, &route.upstream) {
+            (Some(methods), _) => methods
+                .iter()
+                .map(|m| {
+                    Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                        .map_err(|e| format_err!("route({:?})'s method({:?}) invalid: {}", route.url, m, e))
+                })
+                .collect::<crate::how::Result<Vec<_>>>()?,
+            (None, Some(_)) => return Ok(None),
+            (None, None) => vec![Method::GET, Method::HEAD, Method::POST],
+        };
+
+        Ok(Some(Self::new(methods, route.upstream.is_some(), allow_post)))
+    }
+
+    // the fs_handler not supports OPTIONS
+    fn new(all: Vec<Method>, is_upstream: bool, allow_post: bool) -> Self {
+        let mut methods = Vec::with_capacity(all.len());
+        for m in all {
+            if (is_upstream || m != Method::OPTIONS) && !methods.contains(&m) {
+                methods.push(m);
+            }
+        }
+
+        let mut allow = methods
+            .iter()
+            .filter(|m| allow_post || *m != Method::POST)
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>();
+        if !methods.contains(&Method::OPTIONS) {
+            allow.push(Method::OPTIONS.as_str());
+        }
+        let allow = allow.join(", ").parse().expect("MethodsFilter.allow");
+
+        Self { methods, allow }
+    }
+}
+
+impl MiddleWare for MethodsFilter {
+    fn before(&self, req: &Request, addr: &SocketAddr, _ctx: &mut Ctx) -> Result<(), Response> {
+        let method = req.method();
+        if self.methods.contains(method) {
+            return Ok(());
+        }
+
+        if method == Method::OPTIONS {
+            let mut resp = response()
+                .status(StatusCode::NO_CONTENT)
+                .header(header::ALLOW, self.allow.clone());
+            // the preflight of CORS
+            if req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) {
+                resp = resp.header(header::ACCESS_CONTROL_ALLOW_METHODS, self.allow.clone());
+            }
+            return Err(resp.body(Default::default()).unwrap());
+        }
+
+        Err(method_not_allowed_handler_sync(self.allow.to_str().unwrap(), req, addr).unwrap())
+    }
+}
+
+#[test]
+fn methods_filter_test() {
+    let allow = |route: &Route| MethodsFilter::for_route(route).unwrap().map(|f| f.allow);
+
+    assert_eq!(allow(&Route::new("/", ".")).unwrap(), "GET, HEAD, OPTIONS");
+    let filter = MethodsFilter::for_route(&Route::new("/", ".")).unwrap().unwrap();
+    assert_eq!(filter.methods, vec![Method::GET, Method::HEAD, Method::POST]);
+    assert_eq!(allow(&Route::new("/", ".").upload(true)).unwrap(), "GET, HEAD, POST, OPTIONS");
+    assert!(allow(&Route::new_upstream("/", "http://127.0.0.1:3000")).is_none());
+
+    let mut route = Route::new("/", ".").upload(true);
+    route.methods = Some(vec!["get".to_owned(), "OPTIONS".to_owned()]);
+    assert_eq!(allow(&route).unwrap(), "GET, OPTIONS");
+    route.upstream = Some("http://127.0.0.1:3000".to_owned());
+    let filter = MethodsFilter::for_route(&route).unwrap().unwrap();
+    assert_eq!(filter.methods, vec![Method::GET, Method::OPTIONS]);
+    assert_eq!(filter.allow, "GET, OPTIONS");
+
+    route.methods = Some(vec!["GET POST".to_owned()]);
+    assert!(MethodsFilter::for_route(&route).is_err());
+}
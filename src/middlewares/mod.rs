@@ -1,4 +1,8 @@
 pub mod auth;
 pub mod cors;
+pub mod error_pages;
+pub mod headers;
 pub mod logger;
+pub mod methods;
 pub mod path;
+pub mod rewrite;
@@ -20,32 +20,7 @@ impl MiddleWare for PathNormalizer {
         }
 
         let reqpath = ctx.get::<ctxs::ReqPath>().unwrap();
-        let reqpath_components_raw = reqpath.split('/').filter(|c| !c.is_empty() && c != &".");
-
-        let reqpath_components = reqpath_components_raw
-            .fold(Ok(vec![]), |cs, c| {
-                cs.and_then(move |mut cs| match (!cs.is_empty(), c == "..") {
-                    (_, false) => {
-                        cs.push(c);
-                        Ok(cs)
-                    }
-                    (true, true) => {
-                        cs.pop();
-                        Ok(cs)
-                    }
-                    (false, true) => Err(cs),
-                })
-            })
-            .unwrap_or_else(|e| e);
-
-        let mut reqpath_expected = String::with_capacity(reqpath.len());
-        for component in &reqpath_components {
-            reqpath_expected.push('/');
-            reqpath_expected.push_str(component);
-        }
-        if reqpath.ends_with('/') {
-            reqpath_expected.push('/');
-        }
+        let (reqpath_components, reqpath_expected) = normalize(reqpath);
 
         debug!("reqpath: {} -> {}, {:?}", reqpath, reqpath_expected, reqpath_components);
 
@@ -66,3 +41,35 @@ impl MiddleWare for PathNormalizer {
         Ok(())
     }
 }
+
+/// Resolve the `.` and `..` of the path
+pub fn normalize(reqpath: &str) -> (Vec<&str>, String) {
+    let reqpath_components_raw = reqpath.split('/').filter(|c| !c.is_empty() && c != &".");
+
+    let reqpath_components = reqpath_components_raw
+        .fold(Ok(vec![]), |cs, c| {
+            cs.and_then(move |mut cs| match (!cs.is_empty(), c == "..") {
+                (_, false) => {
+                    cs.push(c);
+                    Ok(cs)
+                }
+                (true, true) => {
+                    cs.pop();
+                    Ok(cs)
+                }
+                (false, true) => Err(cs),
+            })
+        })
+        .unwrap_or_else(|e| e);
+
+    let mut reqpath_expected = String::with_capacity(reqpath.len());
+    for component in &reqpath_components {
+        reqpath_expected.push('/');
+        reqpath_expected.push_str(component);
+    }
+    if reqpath.ends_with('/') {
+        reqpath_expected.push('/');
+    }
+
+    (reqpath_components, reqpath_expected)
+}
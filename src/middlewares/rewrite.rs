@@ -0,0 +1,160 @@
+use hyper::StatusCode;
+use regex::Regex;
+
+use std::net::SocketAddr;
+
+use crate::{
+    base::{
+        ctx::{ctxs, Ctx},
+        middleware::MiddleWare,
+        Request, Response,
+    },
+    config::{Rewrite, RewriteKind},
+    handlers::exception::redirect_status_handler_sync,
+    middlewares::path::normalize,
+    tools::url_for_path,
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    regex: Regex,
+    target: String,
+    // None is internal
+    redirect: Option<StatusCode>,
+}
+
+/// The first rule matched the normalized path wins,
+/// the internal rewrite changes the path used to match routes, both merge the query of target and request
+#[derive(Debug, Default, Clone)]
+pub struct Rewriter {
+    rules: Vec<Rule>,
+}
+
+impl Rewriter {
+    pub fn new(rewrites: &[Rewrite]) -> crate::how::Result<Self> {
+        let rules = rewrites
+            .iter()
+            .map(|r| {
+                let regex = Regex::new(&r.regex).map_err(|e| format_err!("rewrite({:?})'s regex invalid: {}", r.regex, e))?;
+                let redirect = match r.kind {
+                    RewriteKind::Internal => None,
+                    RewriteKind::Redirect(code) => Some(StatusCode::from_u16(code)?),
+                };
+                Ok(Rule {
+                    regex,
+                    redirect,
+                    target: r.target.clone(),
+                })
+            })
+            .collect::<crate::how::Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    fn rewrite(&self, reqpath: &str) -> Option<(&Rule, String)> {
+        self.rules.iter().find_map(|rule| {
+            rule.regex.captures(reqpath).map(|caps| {
+                let mut dest = String::with_capacity(rule.target.len());
+                caps.expand(&rule.target, &mut dest);
+                (rule, dest)
+            })
+        })
+    }
+}
+
+impl MiddleWare for Rewriter {
+    fn before(&self, req: &Request, _addr: &SocketAddr, ctx: &mut Ctx) -> Result<(), Response> {
+        if self.rules.is_empty() || ctx.get::<ctxs::IsProxy>().is_some() {
+            return Ok(());
+        }
+
+        let reqpath = ctx.get::<ctxs::ReqPath>().unwrap();
+        let (rule, dest) = match self.rewrite(reqpath) {
+            Some(rewrited) => rewrited,
+            None => return Ok(()),
+        };
+        debug!("rewrite: {} -> {} ({:?})", reqpath, dest, rule.redirect);
+
+        let (path, query) = match dest.find('?') {
+            Some(idx) => (&dest[..idx], Some(&dest[idx + 1..])),
+            None => (dest.as_str(), None),
+        };
+
+        if let Some(code) = rule.redirect {
+            // a absolute url is not encoded again
+            let mut location = if path.contains("://") {
+                path.to_owned()
+            } else {
+                url_for_path(path)
+            };
+
+            if let Some(query) = merge_query(query, req.uri().query()) {
+                location.push('?');
+                location.push_str(&query);
+            }
+
+            return Err(redirect_status_handler_sync(code, location).unwrap());
+        }
+
+        // the same as PathNormalizer
+        let (components, path) = normalize(path);
+        let components = components.into_iter().map(|c| c.to_owned()).collect::<ctxs::ReqPathCs>();
+        let path = if path.is_empty() { "/".to_owned() } else { path };
+
+        ctx.insert::<ctxs::ReqPathCs>(components);
+        ctx.insert::<ctxs::ReqPath>(path);
+        if query.is_some() {
+            ctx.insert::<ctxs::ReqQuery>(merge_query(query, req.uri().query()));
+        }
+
+        Ok(())
+    }
+}
+
+// the query of target first, then the request's
+fn merge_query(target: Option<&str>, req: Option<&str>) -> Option<String> {
+    let querys = target.into_iter().chain(req).filter(|q| !q.is_empty()).collect::<Vec<_>>();
+    if querys.is_empty() {
+        None
+    } else {
+        Some(querys.join("&"))
+    }
+}
+
+#[test]
+fn rewriter_test() {
+    let rewrite = |regex: &str, target: &str, kind| Rewrite {
+        regex: regex.to_owned(),
+        target: target.to_owned(),
+        kind,
+    };
+    let rewriter = Rewriter::new(&[
+        rewrite("^/old/(.*)$", "/new/$1", RewriteKind::Redirect(301)),
+        rewrite("^/docs/(?P<page>[^/]+)$", "/documents/${page}.html", RewriteKind::Internal),
+        rewrite(
+            "^/zh/(?P<page>[^/]+)$",
+            "/documents/${page}.html?lang=zh",
+            RewriteKind::Internal,
+        ),
+        rewrite("^/docs/", "/never", RewriteKind::Internal),
+    ])
+    .unwrap();
+
+    let dest = |path: &str| {
+        rewriter
+            .rewrite(path)
+            .map(|(rule, dest)| (rule.redirect.map(|c| c.as_u16()), dest))
+    };
+    assert_eq!(dest("/old/a/b.txt"), Some((Some(301), "/new/a/b.txt".to_owned())));
+    assert_eq!(dest("/docs/intro"), Some((None, "/documents/intro.html".to_owned())));
+    assert_eq!(dest("/docs/a/b"), Some((None, "/never".to_owned())));
+    assert_eq!(dest("/other"), None);
+    assert_eq!(dest("/zh/intro"), Some((None, "/documents/intro.html?lang=zh".to_owned())));
+
+    assert_eq!(merge_query(Some("lang=zh"), Some("a=1")), Some("lang=zh&a=1".to_owned()));
+    assert_eq!(merge_query(Some(""), Some("a=1")), Some("a=1".to_owned()));
+    assert_eq!(merge_query(Some("lang=zh"), None), Some("lang=zh".to_owned()));
+    assert_eq!(merge_query(None, Some("")), None);
+
+    assert!(Rewriter::new(&[rewrite("(", "/", RewriteKind::Internal)]).is_err());
+}
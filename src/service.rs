@@ -1,73 +1,57 @@
 use futures::{
+    channel::oneshot,
     future::{select, Either},
-    Future, FutureExt,
+    FutureExt,
 };
 use hyper::server::conn::Http;
-use tokio::{
-    runtime::{Builder, Runtime},
-    signal::ctrl_c,
-    task::JoinHandle,
+use tokio::{net::TcpListener, runtime::Builder, signal::ctrl_c, task::JoinHandle};
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
 };
 
 use crate::{
     base::{Router, Server},
-    config::{Config, TlsAcceptor},
-    consts,
-    how::Result,
+    config::{Config, Route, TlsAcceptor},
+    daemon::{notify_startup, remove_pid_file},
+    how::{Error, Result},
     stat::stat_print,
 };
 
 pub struct State {
     tls: Option<TlsAcceptor>,
     config: Config,
-    runtime: Runtime,
-    router: Router,
+    router: RwLock<Arc<Router>>,
+    // the last config loaded successfully, the next reload is compared with it
+    loaded: RwLock<Arc<Config>>,
     http: Http,
 }
 
-pub static mut STATE: Option<State> = None;
-
-pub type GlobalState = &'static State;
+pub type SharedState = Arc<State>;
 
 impl State {
-    pub fn new(config: Config) -> Result<Self> {
+    pub fn new(mut config: Config) -> Result<Self> {
         let tls = config.load_cert()?;
+        config.hsts_header = config.cert.as_ref().and_then(|c| c.hsts.as_ref()).and_then(|h| h.to_header());
+
         let mut http = Http::new();
         http.http1_keep_alive(config.keep_alive);
-        let router = Router::new(&config)?;
-        let runtime = Builder::new()
-            .threaded_scheduler()
-            .core_threads(num_cpus::get() * 2 + 1)
-            .thread_name("tok")
-            .enable_all()
-            .build()?;
+        // the body chunks must reach the IO in place, the placeholders of sendfile are recognized by address,
+        // the auto strategy flattens(copies) them into one buffer if the IO doesn't use the vectored write
+        http.http1_writev(true);
+        let router = RwLock::new(Arc::new(Router::new(&config)?));
+        let loaded = RwLock::new(Arc::new(config.clone()));
 
         Ok(Self {
             config,
-            runtime,
             router,
+            loaded,
             tls,
             http,
         })
     }
-    pub fn into_global(self) -> GlobalState {
-        unsafe {
-            STATE = Some(self);
-            assert_eq!(STATE.as_ref().unwrap().config, Self::global().config);
-            STATE.as_ref().unwrap()
-        }
-    }
-    pub fn global() -> GlobalState {
-        unsafe { STATE.as_ref().expect("Global State is None") }
-    }
 
-    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
-    where
-        F: Future + Send + 'static,
-        F::Output: Send + 'static,
-    {
-        self.runtime.spawn(future)
-    }
     pub fn config(&self) -> &Config {
         &self.config
     }
@@ -77,8 +61,204 @@ impl State {
     pub fn http(&self) -> &Http {
         &self.http
     }
-    pub fn router(&self) -> &Router {
-        &self.router
+    pub fn router(&self) -> Arc<Router> {
+        self.router.read().expect("router lock poisoned").clone()
+    }
+
+    /// Re-parse the config file and swap the `Router` for new requests, keep the old one if failed,
+    /// warn the changes since the last loaded config which take effect after restart
+    pub fn reload(&self) -> Result<()> {
+        let path = self
+            .config
+            .config_path
+            .as_ref()
+            .ok_or_else(|| format_err!("not started with a config file"))?;
+
+        let config = Config::load_from_file(path).map_err(Error::msg)?;
+        let router = Router::new(&config)?;
+
+        let loaded = self.loaded.read().expect("loaded config lock poisoned").clone();
+        for field in restart_required(&loaded, &config) {
+            warn!("the change of {} will take effect after restart", field);
+        }
+
+        *self.router.write().expect("router lock poisoned") = Arc::new(router);
+        config
+            .routes
+            .values()
+            .for_each(|r| info!("reloaded route: {:?} -> {:?}", r.url, r.target()));
+        config.vhosts.iter().for_each(|(host, routes)| {
+            routes
+                .values()
+                .for_each(|r| info!("reloaded route: {} {:?} -> {:?}", host, r.url, r.target()))
+        });
+        *self.loaded.write().expect("loaded config lock poisoned") = Arc::new(config);
+
+        Ok(())
+    }
+}
+
+// the fields read from `State::config`, the `trustedProxies` is the router's
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    let cert = |c: &Config| c.cert.as_ref().map(|c| (c.pub_.clone(), c.key.clone()));
+    let hsts = |c: &Config| c.cert.as_ref().and_then(|c| c.hsts.clone());
+    let fields = [
+        ("addr", old.addr != new.addr),
+        ("cert", cert(old) != cert(new)),
+        ("hsts", hsts(old) != hsts(new)),
+        ("keepAlive", old.keep_alive != new.keep_alive),
+        ("sendfile", old.sendfile != new.sendfile),
+        ("cacheSecs", old.cache_secs != new.cache_secs),
+        ("compressLevel", old.compress_level != new.compress_level),
+        ("brotliLevel", old.brotli_level != new.brotli_level),
+        ("zstdLevel", old.zstd_level != new.zstd_level),
+        ("magicLimit", old.magic_limit != new.magic_limit),
+        ("workerThreads", old.worker_threads != new.worker_threads),
+        ("blockingThreads", old.blocking_threads != new.blocking_threads),
+        ("threadStackSize", old.thread_stack_size != new.thread_stack_size),
+    ];
+
+    fields.iter().filter(|(_, changed)| *changed).map(|(name, _)| *name).collect()
+}
+
+#[test]
+fn restart_required_test() {
+    use crate::config::Cert;
+
+    let old = Config::default();
+    let mut new = old.clone();
+    new.routes.clear();
+    new.auth = Some(Default::default());
+    assert!(restart_required(&old, &new).is_empty());
+
+    // takes effect by the reloaded router
+    new.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    assert!(restart_required(&old, &new).is_empty());
+
+    new.keep_alive = !old.keep_alive;
+    new.worker_threads = old.worker_threads + 1;
+    assert_eq!(restart_required(&old, &new), vec!["keepAlive", "workerThreads"]);
+
+    let cert = Cert {
+        pub_: "fht2p.pem".to_owned(),
+        key: "fht2p.key".to_owned(),
+        hsts: None,
+    };
+    let old = Config {
+        cert: Some(cert.clone()),
+        ..Default::default()
+    };
+    let mut new = old.clone();
+    new.cert.as_mut().unwrap().hsts = Some(Default::default());
+    assert_eq!(restart_required(&old, &new), vec!["hsts"]);
+    new.cert = Some(Cert {
+        key: "fht2p2.key".to_owned(),
+        ..cert
+    });
+    assert_eq!(restart_required(&old, &new), vec!["cert"]);
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(state: SharedState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("listen SIGHUP failed, reload disabled: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        warn!("SIGHUP catched, reload config: {:?}", state.config().config_path);
+
+        match state.reload() {
+            Ok(()) => warn!("reload config ok"),
+            Err(e) => error!("reload config failed, keep running with the old one: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_sighup(_state: SharedState) {}
+
+/// Build a server runs on the current tokio runtime, several servers can live in one process
+///
+/// ```no_run
+/// # async fn run() -> fht2plib::how::Result<()> {
+/// use fht2plib::{config::Route, service::ServerBuilder};
+///
+/// let server = ServerBuilder::default()
+///     .addr("127.0.0.1:0".parse()?)
+///     .route(Route::new("/", "./"))
+///     .serve()
+///     .await?;
+/// println!("serving at {}", server.addr());
+///
+/// server.shutdown().await
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ServerBuilder {
+    config: Config,
+}
+
+impl ServerBuilder {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+    /// the port 0 will be replaced by the port system assigned
+    pub fn addr(mut self, addr: SocketAddr) -> Self {
+        self.config.addr = addr;
+        self
+    }
+    /// add or replace the route with the same url
+    pub fn route(mut self, route: Route) -> Self {
+        self.config.routes.insert(route.url.clone(), route);
+        self
+    }
+    pub fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    /// Bind the address and spawn the server on the current tokio runtime
+    pub async fn serve(self) -> Result<ServerHandle> {
+        let mut config = self.config;
+        let tcp = TcpListener::bind(config.addr).await?;
+        config.addr = tcp.local_addr()?;
+
+        let state = Arc::new(State::new(config)?);
+        let (sender, receiver) = oneshot::channel::<()>();
+        let task = tokio::spawn(Server::run(tcp, state.clone(), receiver.map(|_| ())));
+
+        Ok(ServerHandle {
+            state,
+            task,
+            shutdown: sender,
+        })
+    }
+}
+
+pub struct ServerHandle {
+    state: SharedState,
+    task: JoinHandle<Result<()>>,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl ServerHandle {
+    /// the address bound actually
+    pub fn addr(&self) -> SocketAddr {
+        self.state.config().addr
+    }
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    /// Stop accepting new connections, the accepted connections will finish in background
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown.send(()).ok();
+        self.task.await?
     }
 }
 
@@ -88,28 +268,61 @@ pub trait Service {
 
 impl Service for Config {
     fn startup(self) -> Result<()> {
-        consts::SERVER_ADDR.set(self.addr);
-        consts::MAGIC_LIMIT.set(self.magic_limit);
-
-        let state = State::new(self)?.into_global();
-        let this = state.config();
-        stat_print(&this.addr, this.cert.is_some(), this.routes.values(), this.show_qrcode);
+        let mut rt = Builder::new()
+            .threaded_scheduler()
+            .core_threads(self.worker_threads)
+            .max_threads(self.worker_threads + self.blocking_threads)
+            .thread_stack_size(self.thread_stack_size)
+            .thread_name("tok")
+            .enable_all()
+            .build()?;
 
-        let mut rt = Builder::new().basic_scheduler().enable_all().build()?;
+        let pid_file = self.pid_file.clone();
 
-        rt.block_on(async move {
-            let http = Server::run(state);
-            let ctrlc = ctrl_c();
+        let rest = rt.block_on(async move {
+            let mut server = ServerBuilder::new(self).serve().await?;
+            notify_startup(Ok::<_, Error>(()));
+            stat_print(server.state().config());
+            tokio::spawn(reload_on_sighup(server.state().clone()));
 
-            match select(http.boxed(), ctrlc.boxed()).await {
-                Either::Left((http, _)) => {
-                    error!("http listen finish: {:?}", http?);
-                }
-                Either::Right((ctrlc, _)) => {
-                    warn!("ctrlc catched: {:?}, will exit", ctrlc);
+            let signals = select(ctrl_c().boxed(), terminate().boxed());
+            match select(signals, &mut server.task).await {
+                Either::Left((Either::Left((ctrlc, _)), _)) => warn!("ctrlc catched: {:?}, will exit", ctrlc),
+                Either::Left((Either::Right(((), _)), _)) => warn!("SIGTERM catched, will exit"),
+                // the accept loop failed or panicked, nothing is serving
+                Either::Right((stopped, _)) => {
+                    stopped??;
+                    return Err(format_err!("{} stopped unexpectedly", server.addr()));
                 }
             }
-            Ok(())
-        })
+
+            server.shutdown().await
+        });
+
+        if let Some(pid_file) = pid_file {
+            remove_pid_file(&pid_file);
+        }
+
+        rest
     }
 }
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            term.recv().await;
+        }
+        Err(e) => {
+            error!("listen SIGTERM failed: {}", e);
+            futures::future::pending::<()>().await
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate() {
+    futures::future::pending().await
+}
@@ -1,7 +1,9 @@
 use systemstat::data::IpAddr as StatIpAddr;
 use systemstat::{Platform, System};
 
-use crate::config::Route;
+use bytesize::ByteSize;
+
+use crate::config::{Config, Route};
 use crate::consts;
 
 use std::cmp::Ordering::*;
@@ -10,7 +12,8 @@ use std::net::{IpAddr, SocketAddr};
 
 const TIP: &str = "You can visit:";
 
-pub fn stat_print<'a>(addr: &SocketAddr, tls: bool, routes: impl Iterator<Item = &'a Route>, show_qrcode: bool) {
+pub fn stat_print(config: &Config) {
+    let addr = &config.addr;
     println!(
         "{}/{} Serving at {}:{} for:",
         consts::NAME,
@@ -19,14 +22,58 @@ pub fn stat_print<'a>(addr: &SocketAddr, tls: bool, routes: impl Iterator<Item =
         addr.port()
     );
 
-    routes.for_each(|r| println!("   {:?} -> {:?}", r.url, r.path));
+    config
+        .routes
+        .values()
+        .for_each(|r| println!("   {:?} -> {:?}{}", r.url, r.target(), route_overrides(r, config)));
+    config.vhosts.iter().for_each(|(host, routes)| {
+        routes
+            .values()
+            .for_each(|r| println!("   {} {:?} -> {:?}{}", host, r.url, r.target(), route_overrides(r, config)))
+    });
+    println!(
+        "Cache: {}s, compress: {}, magic: {}",
+        config.cache_secs,
+        config.compress_level,
+        ByteSize::b(config.magic_limit).to_string_as(true)
+    );
+
+    println!(
+        "Threads: worker {}, blocking {}, stack {}",
+        config.worker_threads,
+        config.blocking_threads,
+        ByteSize::b(config.thread_stack_size as u64).to_string_as(true)
+    );
 
     println!("{}", TIP);
 
-    let proto = if tls { "https" } else { "http" };
-    print_addrs(addr, proto, show_qrcode)
+    let proto = if config.cert.is_some() { "https" } else { "http" };
+    print_addrs(addr, proto, config.show_qrcode)
         .map_err(|e| error!("print_addrs faield: {:?}", e))
-        .unwrap_or_else(|_| print_addr(&addr.ip(), addr.port(), proto, show_qrcode))
+        .unwrap_or_else(|_| print_addr(&addr.ip(), addr.port(), proto, config.show_qrcode))
+}
+
+// the settings differ from the global ones
+fn route_overrides(route: &Route, config: &Config) -> String {
+    let mut overrides = vec![];
+    if route.cache_secs.is_some() {
+        overrides.push(format!("cache: {}s", route.cache_secs(config)));
+    }
+    if route.compress_level.is_some() {
+        overrides.push(format!("compress: {}", route.compress_level(config)));
+    }
+    if route.magic_limit.is_some() {
+        overrides.push(format!(
+            "magic: {}",
+            ByteSize::b(route.magic_limit(config)).to_string_as(true)
+        ));
+    }
+
+    if overrides.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", overrides.join(", "))
+    }
 }
 
 fn print_addr(adr: &IpAddr, port: u16, proto: &str, show_qrcode: bool) {
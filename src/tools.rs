@@ -32,6 +32,78 @@ pub fn url_component_encode(input: &str) -> PercentEncode {
     percent_encode(input.as_bytes(), PATH_ENCODE_SET)
 }
 
+/// The list of the `Accept` like headers, `gzip;q=0.8, br` or `text/html, */*;q=0.8`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QValues(
+    // (value in lowercase, q-value in thousandths)
+    Vec<(String, u16)>,
+);
+
+impl QValues {
+    pub fn parse(s: &str) -> Self {
+        let values = s
+            .split(',')
+            .filter_map(|value| {
+                let mut params = value.split(';').map(|s| s.trim());
+                let value = params.next().filter(|c| !c.is_empty())?.to_ascii_lowercase();
+                // the invalid q-value is taken as 0
+                let q = params
+                    .find_map(|p| p.strip_prefix("q=").or_else(|| p.strip_prefix("Q=")))
+                    .map(|q| parse_qvalue(q).unwrap_or(0))
+                    .unwrap_or(1000);
+                Some((value, q))
+            })
+            .collect();
+        Self(values)
+    }
+
+    /// The q-value of the `value` listed, case-insensitive
+    pub fn get(&self, value: &str) -> Option<u16> {
+        self.0.iter().find(|(v, _)| v.eq_ignore_ascii_case(value)).map(|(_, q)| *q)
+    }
+
+    /// The q-value of the media type, or its `type/*`, or `*/*`
+    pub fn media(&self, media: &str) -> u16 {
+        let range = media.split('/').next().map(|t| format!("{}/*", t)).unwrap_or_default();
+        self.get(media)
+            .or_else(|| self.get(&range))
+            .or_else(|| self.get("*/*"))
+            .unwrap_or(0)
+    }
+}
+
+// `0.5` -> 500, up to three digits after the point, 0~1
+fn parse_qvalue(q: &str) -> Option<u16> {
+    let (int, frac) = match q.find('.') {
+        Some(idx) => (&q[..idx], &q[idx + 1..]),
+        None => (q, ""),
+    };
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac = format!("{:0<3}", frac).parse::<u16>().ok()?;
+    match int {
+        "0" => Some(frac),
+        "1" if frac == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+#[test]
+fn qvalues_test() {
+    let accept = QValues::parse("text/html;level=1;q=0.9, application/*;Q=0.5, */*;q=0.1, image/png;q=2");
+    assert_eq!(accept.get("TEXT/HTML"), Some(900));
+    assert_eq!(accept.get("text/plain"), None);
+    assert_eq!(accept.media("text/html"), 900);
+    assert_eq!(accept.media("application/json"), 500);
+    assert_eq!(accept.media("text/plain"), 100);
+    assert_eq!(accept.media("image/png"), 0);
+    assert_eq!(QValues::parse("").media("text/html"), 0);
+    assert_eq!(parse_qvalue("0.25"), Some(250));
+    assert_eq!(parse_qvalue("1.000"), Some(1000));
+    assert_eq!(parse_qvalue("0.0001"), None);
+}
+
 #[test]
 fn test_url_path_decode() {
     assert_eq!(
@@ -24,16 +24,24 @@ pub struct BaseTemplate<'a> {
     pub mkdir: bool,
 }
 impl<'a> BaseTemplate<'a> {
-    pub fn new(title: &'a str, h1: &'a str, parent: &'a str, client: &'a SocketAddr, upload: bool, mkdir: bool) -> Self {
+    pub fn new(
+        title: &'a str,
+        h1: &'a str,
+        parent: &'a str,
+        client: &'a SocketAddr,
+        server: &'a SocketAddr,
+        upload: bool,
+        mkdir: bool,
+    ) -> Self {
         BaseTemplate {
             title,
             h1,
             parent,
             client,
+            server,
             upload,
             mkdir,
             css: include_str!(concat!(env!("OUT_DIR"), "/fht2p.css")),
-            server: consts::SERVER_ADDR.get(),
             url: consts::URL,
             name: consts::NAME,
             version: env!("CARGO_PKG_VERSION"),
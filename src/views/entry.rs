@@ -6,6 +6,8 @@ use std::io;
 use std::path::Path;
 use std::time;
 
+use crate::base::exclude::Excluder;
+
 pub struct EntryMetadata {
     pub name: String,
     pub size: Option<u64>,
@@ -36,13 +38,22 @@ impl EntryMetadata {
             }),
         })
     }
-    pub fn read_dir<P: AsRef<Path>>(dir: P, follow_links: bool, show_hider: bool, order: &EntryOrder) -> io::Result<Vec<Self>> {
+    pub fn read_dir<P: AsRef<Path>>(
+        dir: P,
+        follow_links: bool,
+        show_hider: bool,
+        excluder: &Excluder,
+        order: &EntryOrder,
+    ) -> io::Result<Vec<Self>> {
         let entries = fs::read_dir(dir)?;
         let mut entries_vec = Vec::new();
         // let mut name_len_max = 0;
         entries.filter_map(|e| e.ok()).for_each(|e| {
             if let Some(d) = EntryMetadata::new(&e, follow_links, show_hider) {
-                entries_vec.push(d)
+                let is_dir = d.typo.map(|t| t.is_dir()).unwrap_or_default();
+                if !excluder.is_excluded(&d.name, is_dir) {
+                    entries_vec.push(d)
+                }
             }
         });
         order.sort(&mut entries_vec);
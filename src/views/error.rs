@@ -12,9 +12,9 @@ pub struct ErrorTemplate<'a> {
 }
 
 impl<'a> ErrorTemplate<'a> {
-    pub fn new(title: &'a str, h1: &'a str, parent: &'a str, client: &'a SocketAddr) -> Self {
+    pub fn new(title: &'a str, h1: &'a str, parent: &'a str, client: &'a SocketAddr, server: &'a SocketAddr) -> Self {
         ErrorTemplate {
-            _parent: BaseTemplate::new(title, h1, parent, client, false, false),
+            _parent: BaseTemplate::new(title, h1, parent, client, server, false, false),
         }
     }
 }
@@ -72,6 +72,7 @@ impl<'a> IndexTemplate<'a> {
         h1: &'a str,
         parent: &'a str,
         client: &'a SocketAddr,
+        server: &'a SocketAddr,
         next: (&'static str, &'static str, &'static str),
         entries: Es,
         upload: bool,
@@ -82,7 +83,7 @@ impl<'a> IndexTemplate<'a> {
     {
         IndexTemplate {
             next,
-            _parent: BaseTemplate::new(title, h1, parent, client, upload, mkdir),
+            _parent: BaseTemplate::new(title, h1, parent, client, server, upload, mkdir),
             entries: entries.map(|entry| Entry::new(entry)).collect::<Vec<Entry<'a>>>(),
         }
     }
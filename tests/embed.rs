@@ -0,0 +1,348 @@
+extern crate fht2plib;
+use fht2plib::{
+    config::Route,
+    service::{ServerBuilder, ServerHandle},
+};
+
+use futures::Future;
+use reqwest::{redirect::Policy, Client};
+use tokio::runtime::Builder;
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut rt = Builder::new().threaded_scheduler().enable_all().build().unwrap();
+    rt.block_on(future)
+}
+
+// on a random port of localhost
+async fn serve(routes: Vec<Route>) -> ServerHandle {
+    routes
+        .into_iter()
+        .fold(
+            ServerBuilder::default().addr("127.0.0.1:0".parse().unwrap()),
+            |builder, route| builder.route(route),
+        )
+        .serve()
+        .await
+        .unwrap()
+}
+
+// the redirects are not followed
+fn client() -> Client {
+    Client::builder().redirect(Policy::none()).build().unwrap()
+}
+
+// cargo test --test embed -- --nocapture
+#[test]
+fn embed_servers_test() {
+    block_on(async {
+        let src = serve(vec![Route::new("/", "src")]).await;
+        let tests = serve(vec![Route::new("/", "tests")]).await;
+
+        assert_ne!(src.addr().port(), 0);
+        assert_ne!(src.addr(), tests.addr());
+
+        let client = client();
+        let get = |addr, path| {
+            let url = format!("http://{}/{}", addr, path);
+            let client = &client;
+            async move {
+                let resp = client.get(&url).send().await.unwrap();
+                (resp.status().as_u16(), resp.text().await.unwrap())
+            }
+        };
+
+        let lib = get(src.addr(), "lib.rs").await;
+        assert_eq!(lib.0, 200);
+        assert_eq!(lib.1, include_str!("../src/lib.rs"));
+        assert_eq!(get(tests.addr(), "lib.rs").await.0, 404);
+        assert_eq!(get(tests.addr(), "embed.rs").await.0, 200);
+
+        let addr = src.addr();
+        src.shutdown().await.unwrap();
+        // not the pooled connection, it lives until idle after the shutdown
+        assert!(Client::new().get(&format!("http://{}/lib.rs", addr)).send().await.is_err());
+        assert_eq!(get(tests.addr(), "embed.rs").await.0, 200);
+
+        tests.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn spa_fallback_test() {
+    block_on(async {
+        let mut route = Route::new("/app/", "tests");
+        route.spa_fallback = Some("embed.rs".to_owned());
+        let server = serve(vec![route]).await;
+
+        let client = client();
+        let get = |path: &str| {
+            let req = client.get(&format!("http://{}/app/{}", server.addr(), path));
+            async move { req.send().await.unwrap() }
+        };
+
+        let resp = get("users/1").await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers()["cache-control"], "no-cache");
+        assert_eq!(resp.text().await.unwrap(), include_str!("embed.rs"));
+
+        assert_eq!(get("users/").await.status().as_u16(), 200);
+        assert_eq!(get("embed.rs").await.headers()["cache-control"], "public, max-age=60");
+        assert_eq!(get("missing.js").await.status().as_u16(), 404);
+        assert_eq!(get("css/missing.css").await.status().as_u16(), 404);
+
+        server.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn index_files_test() {
+    block_on(async {
+        let mut in_place = Route::new("/serve/", "tests").serve_index(true);
+        in_place.index_files = Some(vec!["missing.html".to_owned(), "embed.rs".to_owned()]);
+        let mut redirect = Route::new("/redirect/", "tests").redirect_html(true);
+        redirect.index_files = in_place.index_files.clone();
+        let server = serve(vec![in_place, redirect, Route::new("/default/", "tests").serve_index(true)]).await;
+
+        let client = client();
+        let get = |path: &str| client.get(&format!("http://{}{}", server.addr(), path)).send();
+
+        let resp = get("/serve/").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().await.unwrap(), include_str!("embed.rs"));
+
+        let resp = get("/redirect/").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 301);
+        assert_eq!(resp.headers()["location"], "/redirect/embed.rs");
+
+        // no index.html, the index view
+        let resp = get("/default/").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.text().await.unwrap().contains("embed.rs"));
+
+        server.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn clean_urls_test() {
+    block_on(async {
+        let mut route = Route::new("/", "tests").trim_extensions(true);
+        route.try_extensions = vec![".html".to_owned(), ".rs".to_owned()];
+        let server = serve(vec![route]).await;
+
+        let client = client();
+        let get = |path: &str| client.get(&format!("http://{}{}", server.addr(), path)).send();
+
+        let resp = get("/embed").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().await.unwrap(), include_str!("embed.rs"));
+
+        let resp = get("/embed.rs?a=b").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 301);
+        assert_eq!(resp.headers()["location"], "/embed?a=b");
+
+        assert_eq!(get("/embed/").await.unwrap().status().as_u16(), 404);
+        assert_eq!(get("/missing").await.unwrap().status().as_u16(), 404);
+
+        server.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn clean_urls_candidates_test() {
+    let dir = std::env::temp_dir().join(format!("fht2p-clean-urls-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("中文")).unwrap();
+    std::fs::write(dir.join("中文/about.html"), "html").unwrap();
+    std::fs::write(dir.join("中文/about.htm"), "htm").unwrap();
+
+    block_on(async {
+        let mut route = Route::new("/", dir.to_str().unwrap()).trim_extensions(true);
+        route.try_extensions = vec![".html".to_owned(), ".htm".to_owned()];
+        let server = serve(vec![route]).await;
+
+        let client = client();
+        let get = |path: &str| client.get(&format!("http://{}{}", server.addr(), path)).send();
+
+        let resp = get("/%E4%B8%AD%E6%96%87/about").await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "html");
+
+        let resp = get("/%E4%B8%AD%E6%96%87/about.html").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 301);
+        assert_eq!(resp.headers()["location"], "/%E4%B8%AD%E6%96%87/about");
+
+        // the `/about` is the about.html
+        let resp = get("/%E4%B8%AD%E6%96%87/about.htm").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().await.unwrap(), "htm");
+
+        server.shutdown().await.unwrap();
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn precompressed_test() {
+    let dir = std::env::temp_dir().join(format!("fht2p-precompressed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+    std::fs::write(dir.join("app.js.br"), "br-bytes").unwrap();
+
+    block_on(async {
+        let server = serve(vec![Route::new("/", dir.to_str().unwrap()).precompressed(true)]).await;
+
+        let client = client();
+        let get = |accept: &str, range: Option<&str>| {
+            let mut req = client
+                .get(&format!("http://{}/app.js", server.addr()))
+                .header("Accept-Encoding", accept);
+            if let Some(range) = range {
+                req = req.header("Range", range);
+            }
+            req.send()
+        };
+
+        let resp = get("gzip, br", None).await.unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "br");
+        assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+        assert!(resp.headers()["content-type"].to_str().unwrap().contains("javascript"));
+        let br_etag = resp.headers()["etag"].clone();
+        assert_eq!(resp.text().await.unwrap(), "br-bytes");
+
+        let resp = get("gzip", None).await.unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_ne!(resp.headers()["etag"], br_etag);
+        assert_eq!(resp.text().await.unwrap(), "console.log(1)");
+
+        let resp = get("br", Some("bytes=0-1")).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 0-1/8");
+        assert_eq!(resp.text().await.unwrap(), "br");
+
+        server.shutdown().await.unwrap();
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn upstream_test() {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            assert_eq!(stream.read(&mut byte).await.unwrap(), 1);
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    block_on(async {
+        let backend = serve(vec![Route::new("/", "src")]).await;
+
+        // echo after the upgrade
+        let mut ws = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_addr = ws.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = ws.accept().await.unwrap();
+            let head = read_head(&mut stream).await.to_lowercase();
+            assert!(head.starts_with("get /ws/chat?room=1 http/1.1\r\n"));
+            assert!(head.contains("upgrade: websocket\r\n"));
+            assert!(head.contains("x-forwarded-for: 127.0.0.1\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nhi")
+                .await
+                .unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let server = serve(vec![
+            Route::new_upstream("/api/", format!("http://{}/", backend.addr())),
+            Route::new_upstream("/src/", format!("http://{}", backend.addr())),
+            Route::new_upstream("/ws/", format!("http://{}", ws_addr)),
+        ])
+        .await;
+
+        let client = client();
+        let get = |path: &str| {
+            let req = client.get(&format!("http://{}{}", server.addr(), path));
+            async move { req.send().await.unwrap() }
+        };
+
+        let resp = get("/api/lib.rs").await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().await.unwrap(), include_str!("../src/lib.rs"));
+        assert_eq!(get("/api/nope.rs").await.status().as_u16(), 404);
+        // the prefix is kept: backend's /src/lib.rs
+        assert_eq!(get("/src/lib.rs").await.status().as_u16(), 404);
+        // the redirect of backend is passed to the client
+        assert_eq!(get("/api/handlers").await.status().as_u16(), 301);
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        stream
+            .write_all(b"GET /ws/chat?room=1 HTTP/1.1\r\nHost: fht2p\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n")
+            .await
+            .unwrap();
+        let head = read_head(&mut stream).await;
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        server.shutdown().await.unwrap();
+        backend.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn error_pages_test() {
+    let dir = std::env::temp_dir().join(format!("fht2p-error-pages-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("404.html"), "<h1>custom 404</h1>").unwrap();
+
+    block_on(async {
+        let mut route = Route::new("/", dir.to_str().unwrap());
+        route.error_pages.insert("404".to_owned(), "404.html".to_owned());
+        let server = serve(vec![route, Route::new("/default/", "tests")]).await;
+
+        let client = client();
+        let get = |path: &str, accept: &str| {
+            let req = client
+                .get(&format!("http://{}{}", server.addr(), path))
+                .header("Accept", accept);
+            async move { req.send().await.unwrap() }
+        };
+
+        let resp = get("/missing", "text/html").await;
+        assert_eq!(resp.status().as_u16(), 404);
+        assert!(resp.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        assert_eq!(resp.text().await.unwrap(), "<h1>custom 404</h1>");
+
+        // the built-in one
+        let resp = get("/default/missing", "text/html").await;
+        assert_eq!(resp.status().as_u16(), 404);
+        assert!(!resp.text().await.unwrap().contains("custom 404"));
+
+        let resp = get("/default/missing", "application/json, text/html;q=0.5").await;
+        assert_eq!(resp.status().as_u16(), 404);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let json = serde_json::from_str::<serde_json::Value>(&resp.text().await.unwrap()).unwrap();
+        assert_eq!(json["code"], 404);
+        assert_eq!(json["error"], "Not Found");
+
+        server.shutdown().await.unwrap();
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}